@@ -0,0 +1,64 @@
+//! Times the token walker, the IR walker and the bytecode VM on the same
+//! program. Run with `cargo bench`.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use brainfuck::{Program, Runner};
+
+// Daniel Cristofani's nested loop stress test. It prints a single byte.
+const LONG: &str = "
+>+>+>+>+>++<[>[<+++>-
+ >>>>>
+ >+>+>+>+>++<[>[<+++>-
+   >>>>>
+   >+>+>+>+>++<[>[<+++>-
+     >>>>>
+     +++[->+++++<]>[-]<
+     <<<<<
+   ]<<]>[-]
+   <<<<<
+ ]<<]>[-]
+ <<<<<
+]<<]>.";
+
+const RUNS: u32 = 10;
+
+// The fastest of a few runs, after a warm up.
+fn time(mut run: impl FnMut()) -> Duration {
+    run();
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let program = Program::parse(LONG).unwrap();
+    let rep = program.optimize();
+    let bytecode = program.bytecode();
+    let runner = || Runner::with_io(vec![], vec![], io::empty(), io::sink());
+
+    let tokens = time(|| {
+        let tokens = program.tokens().to_vec();
+        let jumps = program.jumps().to_vec();
+        Runner::with_io(tokens, jumps, io::empty(), io::sink())
+            .run()
+            .unwrap();
+    });
+    let ir = time(|| runner().run_ins(&rep).unwrap());
+    let vm = time(|| runner().run_bytecode(&bytecode).unwrap());
+
+    for (name, took) in [("tokens", tokens), ("ir", ir), ("bytecode", vm)] {
+        println!(
+            "{:<10}{:>10.2?}{:>8.2}x",
+            name,
+            took,
+            tokens.as_secs_f64() / took.as_secs_f64()
+        );
+    }
+}
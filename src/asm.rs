@@ -0,0 +1,302 @@
+//! Prints an [`Assembly`] as source for an external assembler.
+//!
+//! FASM builds executables on its own. NASM and GNU `as` output needs
+//! linking, with `start` as the entry point (`ld -e start`).
+
+use crate::{
+    config::Target,
+    x86::{Assembly, Line, Mem, Op, Operand, Size, ENTRY},
+};
+
+/// The flavour of assembly to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// flat assembler
+    #[default]
+    Fasm,
+    /// Netwide Assembler
+    Nasm,
+    /// GNU `as` in Intel syntax
+    GasIntel,
+    /// GNU `as` in its native AT&T syntax
+    GasAtt,
+}
+
+impl Dialect {
+    /// The extension source files in the dialect usually have.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Dialect::Fasm | Dialect::Nasm => "asm",
+            Dialect::GasIntel | Dialect::GasAtt => "s",
+        }
+    }
+
+    pub fn render(self, asm: &Assembly) -> String {
+        let word = Size::of_addresses(asm.target);
+        let x86_64 = asm.target == Target::X86_64;
+        let mut out = Vec::new();
+
+        match self {
+            Dialect::Fasm => {
+                out.push(match asm.target {
+                    Target::X86 => "format ELF executable 3".to_string(),
+                    Target::X86_64 => "format ELF64 executable 3".to_string(),
+                });
+                out.push(format!("entry {}", ENTRY));
+                out.push("".to_string());
+                out.push("segment readable writeable".to_string());
+            }
+            Dialect::Nasm => {
+                out.push(format!("bits {}", word.bytes() * 8));
+                if x86_64 {
+                    out.push("default rel".to_string());
+                }
+                out.push(format!("global {}", ENTRY));
+                out.push("".to_string());
+                out.push("section .bss".to_string());
+            }
+            Dialect::GasIntel | Dialect::GasAtt => {
+                if self == Dialect::GasIntel {
+                    out.push(".intel_syntax noprefix".to_string());
+                }
+                out.push(format!(".globl {}", ENTRY));
+                out.push("".to_string());
+                out.push(".bss".to_string());
+            }
+        }
+        for (name, size, count) in &asm.bss {
+            out.push(match self {
+                Dialect::Fasm => format!("{} r{} {}", name, &size_name(*size)[..1], count),
+                Dialect::Nasm => format!("{} res{} {}", name, &size_name(*size)[..1], count),
+                _ => format!(
+                    ".balign {}\n{}: .skip {}",
+                    size.bytes(),
+                    name,
+                    size.bytes() * count
+                ),
+            });
+        }
+
+        out.push("".to_string());
+        out.push(
+            match self {
+                Dialect::Fasm => "segment readable executable",
+                Dialect::Nasm => "section .text",
+                _ => ".text",
+            }
+            .to_string(),
+        );
+        let comment = self.comment();
+        for line in &asm.text {
+            out.push(match line {
+                Line::Label(name) => format!("{}:", name),
+                Line::Op(op, None) => format!("    {}", self.op(op, word)),
+                Line::Op(op, Some(text)) => {
+                    format!("    {:<18} {} {}", self.op(op, word), comment, text)
+                }
+                Line::Comment(text) => format!("{} {}", comment, text),
+                Line::Blank => "".to_string(),
+            });
+        }
+
+        if !asm.rodata.is_empty() {
+            out.push("".to_string());
+            out.push(
+                match self {
+                    Dialect::Fasm => "segment readable",
+                    Dialect::Nasm => "section .rodata",
+                    _ => ".section .rodata",
+                }
+                .to_string(),
+            );
+        }
+        for (name, bytes) in &asm.rodata {
+            out.push(match self {
+                Dialect::Fasm | Dialect::Nasm => format!("{} db {}", name, db(bytes)),
+                _ => format!("{}:\n{}", name, ascii(bytes)),
+            });
+        }
+        out.push("".to_string()); // GNU `as` wants the last line ended
+        out.join("\n")
+    }
+
+    fn comment(self) -> &'static str {
+        match self {
+            Dialect::Fasm | Dialect::Nasm => ";",
+            Dialect::GasIntel | Dialect::GasAtt => "#",
+        }
+    }
+
+    fn op(self, op: &Op, word: Size) -> String {
+        if self == Dialect::GasAtt {
+            return att_op(op, word);
+        }
+
+        let operand = |operand: &Operand, size: Size| match operand {
+            Operand::Reg(reg) => reg.name(size).to_string(),
+            Operand::Imm(imm) => imm.to_string(),
+            Operand::Mem(mem) => format!("{} {}", self.size(size), self.mem(mem, word)),
+        };
+        match op {
+            Op::Mov(size, dst, src) => {
+                format!("mov {}, {}", operand(dst, *size), operand(src, *size))
+            }
+            Op::Movzx(dst, src) => format!(
+                "movzx {}, {} {}",
+                dst.name(Size::Dword),
+                self.size(Size::Byte),
+                self.mem(src, word)
+            ),
+            Op::Lea(dst, src) => format!("lea {}, {}", dst.name(word), self.mem(src, word)),
+            Op::Alu(alu, size, dst, src) => format!(
+                "{} {}, {}",
+                alu.mnemonic(),
+                operand(dst, *size),
+                operand(src, *size)
+            ),
+            Op::Jmp(label) => format!("jmp {}", label),
+            Op::J(cond, label) => format!("{} {}", cond.mnemonic(), label),
+            Op::Call(label) => format!("call {}", label),
+            Op::CallPtr(mem) => format!("call {} {}", self.size(word), self.mem(mem, word)),
+            Op::Push(reg) => format!("push {}", reg.name(word)),
+            Op::Pop(reg) => format!("pop {}", reg.name(word)),
+            Op::Ret => "ret".to_string(),
+            Op::Int(n) => format!("int {:#x}", n),
+            Op::Syscall => "syscall".to_string(),
+        }
+    }
+
+    // How the Intel dialects spell out the size of a memory operand.
+    fn size(self, size: Size) -> String {
+        match self {
+            Dialect::GasIntel => format!("{} ptr", size_name(size)),
+            _ => size_name(size).to_string(),
+        }
+    }
+
+    // A memory operand in Intel syntax. FASM and NASM (with `default rel`)
+    // make labels RIP-relative on x86-64 by themselves.
+    fn mem(self, mem: &Mem, word: Size) -> String {
+        match mem {
+            Mem::Base(reg, 0) => format!("[{}]", reg.name(word)),
+            Mem::Base(reg, disp) if *disp > 0 => format!("[{} + {}]", reg.name(word), disp),
+            Mem::Base(reg, disp) => format!("[{} - {}]", reg.name(word), -(*disp as i64)),
+            Mem::Label(label) if self == Dialect::GasIntel && word == Size::Qword => {
+                format!("[rip + {}]", label)
+            }
+            Mem::Label(label) => format!("[{}]", label),
+        }
+    }
+}
+
+fn att_op(op: &Op, word: Size) -> String {
+    let operand = |operand: &Operand, size: Size| match operand {
+        Operand::Reg(reg) => format!("%{}", reg.name(size)),
+        Operand::Imm(imm) => format!("${}", imm),
+        Operand::Mem(mem) => att_mem(mem, word),
+    };
+    // Sources come first, and the size goes on the mnemonic.
+    match op {
+        Op::Mov(size, dst, src) => format!(
+            "mov{} {}, {}",
+            suffix(*size),
+            operand(src, *size),
+            operand(dst, *size)
+        ),
+        Op::Movzx(dst, src) => format!("movzbl {}, %{}", att_mem(src, word), dst.name(Size::Dword)),
+        Op::Lea(dst, src) => format!(
+            "lea{} {}, %{}",
+            suffix(word),
+            att_mem(src, word),
+            dst.name(word)
+        ),
+        Op::Alu(alu, size, dst, src) => format!(
+            "{}{} {}, {}",
+            alu.mnemonic(),
+            suffix(*size),
+            operand(src, *size),
+            operand(dst, *size)
+        ),
+        Op::Jmp(label) => format!("jmp {}", label),
+        Op::J(cond, label) => format!("{} {}", cond.mnemonic(), label),
+        Op::Call(label) => format!("call {}", label),
+        Op::CallPtr(mem) => format!("call *{}", att_mem(mem, word)),
+        Op::Push(reg) => format!("push{} %{}", suffix(word), reg.name(word)),
+        Op::Pop(reg) => format!("pop{} %{}", suffix(word), reg.name(word)),
+        Op::Ret => "ret".to_string(),
+        Op::Int(n) => format!("int ${:#x}", n),
+        Op::Syscall => "syscall".to_string(),
+    }
+}
+
+fn att_mem(mem: &Mem, word: Size) -> String {
+    match mem {
+        Mem::Base(reg, 0) => format!("(%{})", reg.name(word)),
+        Mem::Base(reg, disp) => format!("{}(%{})", disp, reg.name(word)),
+        Mem::Label(label) if word == Size::Qword => format!("{}(%rip)", label),
+        Mem::Label(label) => label.clone(),
+    }
+}
+
+fn suffix(size: Size) -> char {
+    match size {
+        Size::Byte => 'b',
+        Size::Word => 'w',
+        Size::Dword => 'l',
+        Size::Qword => 'q',
+    }
+}
+
+fn size_name(size: Size) -> &'static str {
+    match size {
+        Size::Byte => "byte",
+        Size::Word => "word",
+        Size::Dword => "dword",
+        Size::Qword => "qword",
+    }
+}
+
+// Printable runs of `bytes`, and the rest one at a time, neither including
+// quotes so no escaping is needed.
+fn runs(bytes: &[u8]) -> Vec<Result<String, u8>> {
+    let mut runs = Vec::new();
+    let mut run = String::new();
+    for &b in bytes {
+        if (b' '..=b'~').contains(&b) && b != b'"' && b != b'\\' {
+            run.push(b as char);
+            continue;
+        }
+        if !run.is_empty() {
+            runs.push(Ok(std::mem::take(&mut run)));
+        }
+        runs.push(Err(b));
+    }
+    if !run.is_empty() {
+        runs.push(Ok(run));
+    }
+    runs
+}
+
+// `bytes` as FASM and NASM `db` operands.
+fn db(bytes: &[u8]) -> String {
+    let parts: Vec<String> = runs(bytes)
+        .into_iter()
+        .map(|run| match run {
+            Ok(text) => format!("\"{}\"", text),
+            Err(b) => b.to_string(),
+        })
+        .collect();
+    parts.join(", ")
+}
+
+// `bytes` as GNU `as` directives.
+fn ascii(bytes: &[u8]) -> String {
+    let lines: Vec<String> = runs(bytes)
+        .into_iter()
+        .map(|run| match run {
+            Ok(text) => format!("    .ascii \"{}\"", text),
+            Err(b) => format!("    .byte {}", b),
+        })
+        .collect();
+    lines.join("\n")
+}
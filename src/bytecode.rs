@@ -0,0 +1,124 @@
+//! A compact bytecode for the IR, which [`Runner::run_bytecode`] executes.
+//!
+//! Each instruction is an opcode word followed by its operands, all `i32`s
+//! in one flat vector, and loops jump straight to the word after their
+//! matching bracket, so running it needs no lookups on the side.
+//!
+//! [`Runner::run_bytecode`]: crate::Runner::run_bytecode
+
+use std::fmt;
+
+use crate::{
+    ir::{Instruction, Representation},
+    lex::Span,
+};
+
+/// `ADD offset delta`: adds `delta` to the cell `offset` away.
+pub const ADD: i32 = 0;
+/// `MOVE n`: moves the pointer `n` cells.
+pub const MOVE: i32 = 1;
+/// `PRINT offset`: prints the cell `offset` away.
+pub const PRINT: i32 = 2;
+/// `READ offset`: reads a byte into the cell `offset` away.
+pub const READ: i32 = 3;
+/// `JZ target`: jumps to `target` if the current cell is zero.
+pub const JZ: i32 = 4;
+/// `JNZ target`: jumps to `target` unless the current cell is zero.
+pub const JNZ: i32 = 5;
+
+/// A program ready for the bytecode interpreter.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Bytecode {
+    code: Vec<i32>,
+    // Where each instruction starts, and the code behind it
+    spans: Vec<(usize, Span)>,
+}
+
+impl Bytecode {
+    /// Encodes the IR, resolving every loop to the positions it jumps to.
+    pub fn new(rep: &Representation) -> Self {
+        let mut code = Vec::new();
+        let mut spans = Vec::with_capacity(rep.instructions.len());
+        let mut open = Vec::new();
+
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
+            spans.push((code.len(), *span));
+            match ins {
+                Instruction::Add { offset, delta } => {
+                    code.extend([ADD, operand(*offset), *delta]);
+                }
+                Instruction::Move(n) => code.extend([MOVE, operand(*n)]),
+                Instruction::Print { offset } => code.extend([PRINT, operand(*offset)]),
+                Instruction::Read { offset } => code.extend([READ, operand(*offset)]),
+                Instruction::LoopStart => {
+                    open.push(code.len());
+                    code.extend([JZ, 0]);
+                }
+                Instruction::LoopEnd => {
+                    let start = open.pop().expect("unbalanced loops in IR");
+                    code.extend([JNZ, operand(start + 2)]);
+                    code[start + 1] = operand(code.len());
+                }
+            }
+        }
+
+        Self { code, spans }
+    }
+
+    /// The instructions, one word after another.
+    pub fn code(&self) -> &[i32] {
+        &self.code
+    }
+
+    /// The code behind the instruction starting at `pc`, for errors.
+    pub fn span_at(&self, pc: usize) -> Span {
+        let i = match self.spans.binary_search_by_key(&pc, |(start, _)| *start) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        self.spans[i].1
+    }
+}
+
+/// Lists the instructions, one per line with its position.
+///
+/// ```
+/// use brainfuck::{Bytecode, Program};
+///
+/// let rep = Program::parse("+[-]").unwrap().optimize();
+/// assert_eq!(
+///     Bytecode::new(&rep).to_string(),
+///     "0000  add 0, 1\n0003  jz 10\n0005  add 0, -1\n0008  jnz 5\n"
+/// );
+/// ```
+impl fmt::Display for Bytecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut pc = 0;
+        while pc < self.code.len() {
+            let (name, operands) = match self.code[pc] {
+                ADD => ("add", 2),
+                MOVE => ("move", 1),
+                PRINT => ("print", 1),
+                READ => ("read", 1),
+                JZ => ("jz", 1),
+                JNZ => ("jnz", 1),
+                op => panic!("unknown opcode {}", op),
+            };
+            let args: Vec<String> = self.code[pc + 1..pc + 1 + operands]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+            writeln!(f, "{:04}  {} {}", pc, name, args.join(", "))?;
+            pc += 1 + operands;
+        }
+        Ok(())
+    }
+}
+
+// An offset or position as an operand. Reaching 2^31 would take gigabytes
+// of source.
+fn operand<T: TryInto<i32>>(value: T) -> i32 {
+    value
+        .try_into()
+        .unwrap_or_else(|_| panic!("operand too large for bytecode"))
+}
@@ -0,0 +1,293 @@
+//! Translates the IR to C that any hosted C99 compiler accepts.
+//!
+//! The cell type, tape length and the policies in the [`Config`] show up in
+//! the generated code as a `typedef`, `#define`s and small helper functions,
+//! so the options are easy to find and change afterwards.
+
+use crate::{
+    config::{BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy},
+    error::CodegenError,
+    ir::{Instruction, Representation},
+    lex::Span,
+};
+
+/// Generates a C program from the IR.
+pub struct CGenerator {
+    config: Config,
+}
+
+impl Default for CGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CGenerator {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self { config }
+    }
+
+    pub fn generate(&self, rep: &Representation) -> Result<String, CodegenError> {
+        let bits = match self.config.cell {
+            CellWidth::U8 => 8,
+            CellWidth::U16 => 16,
+            CellWidth::U32 => 32,
+            CellWidth::Unbounded => {
+                return Err(CodegenError::Unsupported(
+                    "Unbounded cells are not supported by the C backend".to_string(),
+                ))
+            }
+        };
+        let grow = self.config.bounds == BoundsPolicy::Grow;
+
+        let mut out = vec![
+            "#include <stddef.h>".to_string(),
+            "#include <stdint.h>".to_string(),
+            "#include <stdio.h>".to_string(),
+            "#include <stdlib.h>".to_string(),
+        ];
+        if grow {
+            out.push("#include <string.h>".to_string());
+        }
+        out.push("".to_string());
+        out.push(format!("#define TAPE_LEN {}", self.config.tape_len.max(1)));
+        out.push(format!("#define CELL_MAX UINT{}_MAX", bits));
+        out.push(format!("typedef uint{}_t cell;", bits));
+        out.push("".to_string());
+        if grow {
+            out.push("static cell *tape;".to_string());
+            out.push("static size_t len = TAPE_LEN;".to_string());
+            out.push("static ptrdiff_t origin; /* where cell 0 is now */".to_string());
+        } else {
+            out.push("static cell tape[TAPE_LEN];".to_string());
+        }
+        out.push("static size_t p;".to_string());
+        out.extend(self.helpers(rep));
+        out.push("".to_string());
+
+        out.push("int main(void) {".to_string());
+        if grow {
+            out.push("    tape = calloc(len, sizeof(cell));".to_string());
+            out.push("    if (!tape) {".to_string());
+            out.push("        fputs(\"Out of memory\\n\", stderr);".to_string());
+            out.push("        return 1;".to_string());
+            out.push("    }".to_string());
+        }
+
+        let mut depth = 1;
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
+            if *ins == Instruction::LoopEnd {
+                depth -= 1;
+            }
+            let line = match ins {
+                Instruction::Add { offset, delta } => {
+                    if self.config.overflow == OverflowPolicy::Trap {
+                        format!(
+                            "add({}, {}, \"{}\");",
+                            self.cell_ptr(*offset, *span),
+                            delta,
+                            span
+                        )
+                    } else if *delta < 0 {
+                        format!("{} -= {};", self.cell(*offset, *span), delta.unsigned_abs())
+                    } else {
+                        format!("{} += {};", self.cell(*offset, *span), delta)
+                    }
+                }
+                Instruction::Move(n) => format!("p = {};", self.seek(*n, *span)),
+                Instruction::Print { offset } => {
+                    format!("putchar({});", self.cell(*offset, *span))
+                }
+                Instruction::Read { offset } => {
+                    format!("input({});", self.cell_ptr(*offset, *span))
+                }
+                Instruction::LoopStart => "while (tape[p]) {".to_string(),
+                Instruction::LoopEnd => "}".to_string(),
+            };
+            out.push(format!("{}{}", "    ".repeat(depth), line));
+            if *ins == Instruction::LoopStart {
+                depth += 1;
+            }
+        }
+
+        out.push("    return 0;".to_string());
+        out.push("}".to_string());
+        out.push("".to_string());
+        Ok(out.join("\n"))
+    }
+
+    // The functions `rep` calls, for the options in the config. Ones it
+    // doesn't call are left out, so compilers don't warn about them.
+    fn helpers(&self, rep: &Representation) -> Vec<String> {
+        let mut offsets = false;
+        let mut moves = false;
+        let mut adds = false;
+        let mut reads = false;
+        for ins in &rep.instructions {
+            match ins {
+                Instruction::Add { offset, .. } => {
+                    adds = true;
+                    offsets |= *offset != 0;
+                }
+                Instruction::Move(_) => moves = true,
+                Instruction::Print { offset } => offsets |= *offset != 0,
+                Instruction::Read { offset } => {
+                    reads = true;
+                    offsets |= *offset != 0;
+                }
+                _ => {}
+            }
+        }
+
+        let trap = self.config.bounds == BoundsPolicy::Trap;
+        let mut out = Vec::new();
+        if offsets || moves {
+            out.push(
+                match self.config.bounds {
+                    BoundsPolicy::Wrap => {
+                        "/* Index of the cell `offset` away from the current one, coming back in on
+   the other end of the tape. */
+static size_t seek(ptrdiff_t offset) {
+    ptrdiff_t i = ((ptrdiff_t)p + offset) % TAPE_LEN;
+    return i < 0 ? i + TAPE_LEN : i;
+}"
+                    }
+                    BoundsPolicy::Trap => {
+                        "/* Index of the cell `offset` away from the current one, stopping the
+   program if it is off the tape. `where` is the code to blame. */
+static size_t seek(ptrdiff_t offset, const char *where) {
+    ptrdiff_t i = (ptrdiff_t)p + offset;
+    if (i < 0 || i >= TAPE_LEN) {
+        fprintf(stderr, \"Runtime error: Pointer out of bounds at %s: cell %td is not on the tape of %lld cells\\n\",
+                where, i, (long long)TAPE_LEN);
+        exit(1);
+    }
+    return i;
+}"
+                    }
+                    BoundsPolicy::Grow => {
+                        "/* Makes room for cell `i`, counted from the start of the tape, at least
+   doubling it, and returns where that cell is now. */
+static size_t grow(ptrdiff_t i) {
+    size_t before = i < 0 ? (size_t)-i : 0;
+    size_t after = i >= (ptrdiff_t)len ? (size_t)i - len + 1 : 0;
+    cell *bigger;
+    if (before && before < len) before = len;
+    if (after && after < len) after = len;
+    bigger = calloc(len + before + after, sizeof(cell));
+    if (!bigger) {
+        fputs(\"Out of memory\\n\", stderr);
+        exit(1);
+    }
+    memcpy(bigger + before, tape, len * sizeof(cell));
+    free(tape);
+    tape = bigger;
+    len += before + after;
+    p += before;
+    origin += before;
+    return i + before;
+}
+
+/* Index of the cell `offset` away from the current one, growing the tape to
+   reach it. */
+static size_t seek(ptrdiff_t offset) {
+    ptrdiff_t i = (ptrdiff_t)p + offset;
+    if (i < 0 || i >= (ptrdiff_t)len) i = grow(i);
+    return i;
+}"
+                    }
+                }
+                .to_string(),
+            );
+        }
+
+        if offsets {
+            // The index comes first, as growing the tape moves it.
+            let (params, args) = if trap {
+                ("ptrdiff_t offset, const char *where", "offset, where")
+            } else {
+                ("ptrdiff_t offset", "offset")
+            };
+            out.push(format!(
+                "static cell *at({}) {{
+    size_t i = seek({});
+    return &tape[i];
+}}",
+                params, args
+            ));
+        }
+
+        if adds && self.config.overflow == OverflowPolicy::Trap {
+            let origin = match self.config.bounds {
+                BoundsPolicy::Grow => " - origin",
+                _ => "",
+            };
+            out.push(format!(
+                "/* Adds `delta` to the cell, stopping the program if it goes below 0 or
+   past CELL_MAX. */
+static void add(cell *c, long long delta, const char *where) {{
+    long long value = (long long)*c + delta;
+    if (value < 0 || value > (long long)CELL_MAX) {{
+        fprintf(stderr, \"Runtime error: Cell overflow at %s: cell %td went %s\\n\", where,
+                c - tape{}, value < 0 ? \"below 0\" : \"past its largest value\");
+        exit(1);
+    }}
+    *c = (cell)value;
+}}",
+                origin
+            ));
+        }
+
+        if reads {
+            let eof = match self.config.eof {
+                EofPolicy::Unchanged => "",
+                EofPolicy::Zero => "\n    else *c = 0;",
+                EofPolicy::Max => "\n    else *c = CELL_MAX;",
+            };
+            out.push(format!(
+                "/* Reads a byte into the cell, flushing first so prompts show up. */
+static void input(cell *c) {{
+    int byte;
+    fflush(stdout);
+    byte = getchar();
+    if (byte != EOF) *c = (cell)byte;{}
+}}",
+                eof
+            ));
+        }
+
+        // A blank line before each
+        out.into_iter()
+            .map(|helper| format!("\n{}", helper))
+            .collect()
+    }
+
+    // The cell `offset` cells away from the current one.
+    fn cell(&self, offset: isize, span: Span) -> String {
+        match offset {
+            0 => "tape[p]".to_string(),
+            _ => format!("*{}", self.cell_ptr(offset, span)),
+        }
+    }
+
+    // A pointer to the cell `offset` cells away from the current one.
+    fn cell_ptr(&self, offset: isize, span: Span) -> String {
+        match (offset, self.config.bounds) {
+            (0, _) => "&tape[p]".to_string(),
+            (_, BoundsPolicy::Trap) => format!("at({}, \"{}\")", offset, span),
+            _ => format!("at({})", offset),
+        }
+    }
+
+    // The index of the cell `offset` cells away from the current one.
+    fn seek(&self, offset: isize, span: Span) -> String {
+        match self.config.bounds {
+            BoundsPolicy::Trap => format!("seek({}, \"{}\")", offset, span),
+            _ => format!("seek({})", offset),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Options shared by the interpreters and the code generators, so every way
+//! of running a program agrees on the dialect of brainfuck it speaks.
+
+/// What `,` stores once the input is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofPolicy {
+    /// Leave the cell as it was
+    #[default]
+    Unchanged,
+    /// Store 0
+    Zero,
+    /// Store the largest cell value, i.e. -1 (255 for 8-bit cells)
+    Max,
+}
+
+/// How much a single cell holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellWidth {
+    /// 0 to 255, wrapping around
+    #[default]
+    U8,
+    /// 0 to 65535, wrapping around
+    U16,
+    /// 0 to 4294967295, wrapping around
+    U32,
+    /// Any integer, negative ones included
+    Unbounded,
+}
+
+impl CellWidth {
+    /// Size of a cell in bytes, or `None` for unbounded cells.
+    pub fn bytes(&self) -> Option<usize> {
+        match self {
+            CellWidth::U8 => Some(1),
+            CellWidth::U16 => Some(2),
+            CellWidth::U32 => Some(4),
+            CellWidth::Unbounded => None,
+        }
+    }
+}
+
+/// Number of cells on the tape unless configured otherwise.
+pub const DEFAULT_TAPE_LEN: usize = 30000;
+
+/// What happens when the pointer leaves the tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundsPolicy {
+    /// Come back in on the other end
+    #[default]
+    Wrap,
+    /// Stop with an error pointing at the offending code
+    Trap,
+    /// Make the tape bigger on that side
+    Grow,
+}
+
+/// What happens when a cell goes below 0 or above its largest value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Come back around from the other end of the range
+    #[default]
+    Wrap,
+    /// Stop with an error pointing at the offending code. Unbounded cells
+    /// never overflow.
+    Trap,
+}
+
+/// The machine compiled programs run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// 32-bit x86 Linux, which 64-bit kernels only run with i386 support
+    #[default]
+    X86,
+    /// 64-bit x86 Linux
+    X86_64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub eof: EofPolicy,
+    pub cell: CellWidth,
+    /// Number of cells, at least one. With `BoundsPolicy::Grow` this is only
+    /// the initial size.
+    pub tape_len: usize,
+    pub bounds: BoundsPolicy,
+    pub overflow: OverflowPolicy,
+    /// Only used when compiling
+    pub target: Target,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            eof: EofPolicy::default(),
+            cell: CellWidth::default(),
+            tape_len: DEFAULT_TAPE_LEN,
+            bounds: BoundsPolicy::default(),
+            overflow: OverflowPolicy::default(),
+            target: Target::default(),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Renders [`Error`]s the way rustc does: what went wrong, the line of
+//! source it went wrong on with a caret under the culprit, and a hint.
+//!
+//! Colours come from `colored`, so `colored::control::set_override(false)`
+//! (or the `NO_COLOR` environment variable) gives plain text for logs.
+
+use std::fmt;
+
+use colored::Colorize;
+
+use crate::{
+    error::{Error, LexError, RuntimeError, SyntaxError},
+    lex::Span,
+};
+
+/// An error along with the source it is about, ready to be printed.
+///
+/// ```
+/// let source = "+[>+";
+/// let err = brainfuck::Program::parse(source).unwrap_err();
+/// colored::control::set_override(false);
+/// let text = brainfuck::Diagnostic::new(&err, "loop.bf", source).to_string();
+/// assert!(text.starts_with("error: unclosed `[`\n --> loop.bf:1:2\n"));
+/// ```
+pub struct Diagnostic<'a> {
+    error: &'a Error,
+    name: &'a str,
+    source: &'a str,
+}
+
+impl<'a> Diagnostic<'a> {
+    /// `name` is how the source is referred to, usually its file name.
+    pub fn new(error: &'a Error, name: &'a str, source: &'a str) -> Self {
+        Self {
+            error,
+            name,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Error::Multiple(errors) = self.error {
+            for error in errors {
+                writeln!(f, "{}", Diagnostic::new(error, self.name, self.source))?;
+            }
+            return writeln!(
+                f,
+                "{}{} aborting due to {} previous errors",
+                "error".red().bold(),
+                ":".bold(),
+                errors.len()
+            );
+        }
+
+        let (title, label, hint) = describe(self.error);
+        writeln!(f, "{}{} {}", "error".red().bold(), ":".bold(), title.bold())?;
+
+        let Some(span) = self.error.span() else {
+            return Ok(());
+        };
+        let line = self.source.lines().nth(span.line - 1).unwrap_or("");
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        writeln!(
+            f,
+            "{}{} {}:{}:{}",
+            gutter,
+            "-->".blue().bold(),
+            self.name,
+            span.line,
+            span.col
+        )?;
+        writeln!(f, "{} {}", gutter, "|".blue().bold())?;
+        writeln!(f, "{} {} {}", number.blue().bold(), "|".blue().bold(), line)?;
+        writeln!(
+            f,
+            "{} {} {}{} {}",
+            gutter,
+            "|".blue().bold(),
+            indent(line, span),
+            carets(line, span).red().bold(),
+            label.red().bold()
+        )?;
+        if let Some(hint) = hint {
+            writeln!(f, "{} {}", gutter, "|".blue().bold())?;
+            writeln!(
+                f,
+                "{} {} {}: {}",
+                gutter,
+                "=".blue().bold(),
+                "help".bold(),
+                hint
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Whitespace reaching up to the column of `span` on `line`, keeping tabs so
+// the caret lines up however wide they are shown.
+fn indent(line: &str, span: Span) -> String {
+    line.chars()
+        .take(span.col - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+// Carets under the characters in the span, cut off at the end of the line
+// for spans that carry on past it.
+fn carets(line: &str, span: Span) -> String {
+    let mut bytes = 0;
+    let width = line
+        .chars()
+        .skip(span.col - 1)
+        .take_while(|c| {
+            let inside = bytes < span.len;
+            bytes += c.len_utf8();
+            inside
+        })
+        .count();
+    "^".repeat(width.max(1))
+}
+
+// The headline of an error, the label under the caret and a hint to fix it.
+fn describe(error: &Error) -> (String, String, Option<&'static str>) {
+    match error {
+        Error::Lex(LexError::UnexpectedChar { ch, .. }) => (
+            format!("unexpected character `{}`", ch.escape_debug()),
+            "not brainfuck".to_string(),
+            Some("use `--lexer standard` to treat anything that isn't a command as a comment"),
+        ),
+        Error::Lex(LexError::UnterminatedComment { .. }) => (
+            "unterminated block comment".to_string(),
+            "this comment is never closed".to_string(),
+            Some("close it with `*/`; comments nest, so every `/*` needs its own"),
+        ),
+        Error::Syntax(SyntaxError::UnmatchedLoopEnd { .. }) => (
+            "unmatched `]`".to_string(),
+            "there is no `[` for this to close".to_string(),
+            Some("remove it, or open the loop with a `[` before it"),
+        ),
+        Error::Syntax(SyntaxError::UnmatchedLoopStart { .. }) => (
+            "unclosed `[`".to_string(),
+            "this loop is never closed".to_string(),
+            Some("add a `]` where the loop should end"),
+        ),
+        Error::Runtime(RuntimeError::OutOfBounds { cell, tape_len, .. }) => (
+            "pointer out of bounds".to_string(),
+            format!("moves to cell {}, off the {} cell tape", cell, tape_len),
+            Some("use `--bounds grow` to grow the tape, or `--tape` for a longer one"),
+        ),
+        Error::Runtime(RuntimeError::TapeFull { cell, .. }) => (
+            "tape full".to_string(),
+            format!("moves to cell {}, further than the tape can grow", cell),
+            Some("the interpreters grow the tape for as long as there is memory; use `--engine bytecode`"),
+        ),
+        Error::Runtime(RuntimeError::Overflow {
+            cell, below_zero, ..
+        }) => (
+            "cell overflow".to_string(),
+            if *below_zero {
+                format!("takes cell {} below 0", cell)
+            } else {
+                format!("takes cell {} past its largest value", cell)
+            },
+            Some("use `--overflow wrap` to let cells wrap around, or `--cell` for wider ones"),
+        ),
+        Error::Codegen(e) => (e.to_string(), String::new(), None),
+        Error::Io(e) => (e.to_string(), String::new(), None),
+        Error::Multiple(_) => unreachable!("rendered one by one"),
+    }
+}
@@ -0,0 +1,135 @@
+//! Writes static Linux executables, so compiling doesn't need an assembler.
+//!
+//! The file has two segments: one mapped read-only and executable with the
+//! headers, the code and the read-only data, and one of zeroed memory for
+//! the tape.
+
+use std::collections::HashMap;
+
+use crate::{
+    config::Target,
+    error::CodegenError,
+    x86::{self, Assembly},
+};
+
+// Where the first segment is loaded, as traditional linkers do.
+const BASE_X86: u64 = 0x0804_8000;
+const BASE_X86_64: u64 = 0x0040_0000;
+const PAGE: u64 = 0x1000;
+
+/// Encodes `asm` and wraps it up as an ELF executable.
+pub fn executable(asm: &Assembly) -> Result<Vec<u8>, CodegenError> {
+    let x86_64 = asm.target == Target::X86_64;
+    let (base, header_len) = match asm.target {
+        Target::X86 => (BASE_X86, 52 + 2 * 32),
+        Target::X86_64 => (BASE_X86_64, 64 + 2 * 56),
+    };
+
+    let mut code = x86::encode(&asm.text, asm.target)?;
+    let text = base + header_len;
+
+    // Read-only data right after the code, in the same segment.
+    let mut symbols = HashMap::new();
+    let mut addr = text + code.bytes.len() as u64;
+    for (label, bytes) in &asm.rodata {
+        symbols.insert(label.clone(), addr);
+        addr += bytes.len() as u64;
+    }
+    let file_len = addr - base;
+
+    // Zeroed memory from the next page on, each piece aligned to its size.
+    let bss = addr.next_multiple_of(PAGE);
+    let mut addr = bss;
+    for (label, size, count) in &asm.bss {
+        let align = size.bytes() as u64;
+        addr = addr.next_multiple_of(align);
+        symbols.insert(label.clone(), addr);
+        addr += (size.bytes() * count) as u64;
+    }
+    let bss_len = addr - bss;
+
+    code.link(text, &symbols)?;
+    let entry = *code
+        .labels
+        .get(x86::ENTRY)
+        .ok_or_else(|| CodegenError::Assembler(format!("there is no `{}` label", x86::ENTRY)))?
+        as u64
+        + text;
+
+    let mut out = Writer {
+        bytes: Vec::with_capacity(file_len as usize),
+        x86_64,
+    };
+    // ELF header
+    out.bytes.extend(b"\x7fELF");
+    out.bytes.push(if x86_64 { 2 } else { 1 }); // 32 or 64-bit
+    out.bytes.push(1); // little endian
+    out.bytes.push(1); // version
+    out.bytes.push(3); // Linux ABI
+    out.bytes.resize(16, 0);
+    out.half(2); // executable
+    out.half(if x86_64 { 0x3e } else { 0x03 });
+    out.word(1); // version
+    out.addr(entry);
+    out.addr(if x86_64 { 64 } else { 52 }); // program headers
+    out.addr(0); // no section headers
+    out.word(0); // flags
+    out.half(if x86_64 { 64 } else { 52 });
+    out.half(if x86_64 { 56 } else { 32 });
+    out.half(2);
+    out.half(0);
+    out.half(0);
+    out.half(0);
+
+    // Program headers: read and execute, then read and write
+    out.segment(5, 0, base, file_len, file_len);
+    out.segment(6, 0, bss, 0, bss_len);
+
+    out.bytes.extend(&code.bytes);
+    for (_, bytes) in &asm.rodata {
+        out.bytes.extend(bytes);
+    }
+    Ok(out.bytes)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    x86_64: bool,
+}
+
+impl Writer {
+    fn half(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn word(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    // An address or offset, as wide as the target's.
+    fn addr(&mut self, value: u64) {
+        if self.x86_64 {
+            self.bytes.extend(value.to_le_bytes());
+        } else {
+            self.word(value as u32);
+        }
+    }
+
+    // A loadable segment; the fields come in a different order in the
+    // 64-bit format.
+    fn segment(&mut self, flags: u32, offset: u64, vaddr: u64, file_len: u64, mem_len: u64) {
+        self.word(1); // PT_LOAD
+        if self.x86_64 {
+            self.word(flags);
+        }
+        self.addr(offset);
+        self.addr(vaddr);
+        self.addr(vaddr);
+        self.addr(file_len);
+        self.addr(mem_len);
+        if !self.x86_64 {
+            self.word(flags);
+        }
+        self.addr(PAGE);
+    }
+}
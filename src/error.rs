@@ -0,0 +1,266 @@
+//! Everything that can go wrong between reading a program and running or
+//! compiling it.
+//!
+//! Each stage has its own error type, and [`Error`] wraps all of them for
+//! APIs such as [`Program`](crate::Program) that go through several stages.
+//! Errors caused by a particular piece of the source carry its [`Span`].
+
+use std::{error, fmt, io};
+
+use crate::lex::Span;
+
+/// Any error from the lexer, the parser, the interpreters or the code
+/// generators.
+#[derive(Debug)]
+pub enum Error {
+    Lex(LexError),
+    Syntax(SyntaxError),
+    Runtime(RuntimeError),
+    Codegen(CodegenError),
+    Io(io::Error),
+    /// Several errors found in one go, in source order
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    /// Wraps up the errors found by a stage that keeps going after the first
+    /// one: a single error stays as it is.
+    pub fn many<E: Into<Error>>(errors: Vec<E>) -> Self {
+        let mut errors: Vec<Error> = errors.into_iter().map(Into::into).collect();
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            Error::Multiple(errors)
+        }
+    }
+
+    /// Where in the source the error is, if it is about a particular spot.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Lex(e) => Some(e.span()),
+            Error::Syntax(e) => Some(e.span()),
+            Error::Runtime(e) => Some(e.span()),
+            Error::Codegen(_) | Error::Io(_) => None,
+            Error::Multiple(errors) => errors.first().and_then(Error::span),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lex(e) => write!(f, "Lex error: {}", e),
+            Error::Syntax(e) => write!(f, "Syntax error: {}", e),
+            Error::Runtime(e) => write!(f, "Runtime error: {}", e),
+            Error::Codegen(e) => write!(f, "Codegen error: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Multiple(errors) => {
+                for e in errors {
+                    writeln!(f, "{}", e)?;
+                }
+                write!(f, "{} errors", errors.len())
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Lex(e) => Some(e),
+            Error::Syntax(e) => Some(e),
+            Error::Runtime(e) => Some(e),
+            Error::Codegen(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Multiple(errors) => errors.first().map(|e| e as _),
+        }
+    }
+}
+
+impl From<LexError> for Error {
+    fn from(e: LexError) -> Self {
+        Error::Lex(e)
+    }
+}
+
+impl From<SyntaxError> for Error {
+    fn from(e: SyntaxError) -> Self {
+        Error::Syntax(e)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl From<CodegenError> for Error {
+    fn from(e: CodegenError) -> Self {
+        Error::Codegen(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// The source contains something that isn't brainfuck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    UnexpectedChar {
+        ch: char,
+        span: Span,
+    },
+    /// A `/*` with no `*/` to match
+    UnterminatedComment {
+        span: Span,
+    },
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnexpectedChar { span, .. } | LexError::UnterminatedComment { span } => *span,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnexpectedChar { ch, span } => {
+                write!(f, "Unexpected character `{}` at {}", ch, span)
+            }
+            LexError::UnterminatedComment { span } => {
+                write!(f, "Unterminated block comment at {}", span)
+            }
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+/// The brackets don't pair up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxError {
+    /// A `]` with no `[` before it
+    UnmatchedLoopEnd { span: Span },
+    /// A `[` that is never closed
+    UnmatchedLoopStart { span: Span },
+}
+
+impl SyntaxError {
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxError::UnmatchedLoopEnd { span } | SyntaxError::UnmatchedLoopStart { span } => {
+                *span
+            }
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxError::UnmatchedLoopEnd { span } => write!(f, "Unmatched LoopEnd at {}", span),
+            SyntaxError::UnmatchedLoopStart { span } => {
+                write!(f, "Unmatched LoopStart at {}", span)
+            }
+        }
+    }
+}
+
+impl error::Error for SyntaxError {}
+
+/// A running program broke one of the rules its [`Config`](crate::Config)
+/// says to trap on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// The pointer left a tape of `tape_len` cells for `cell`, counted from
+    /// where it started.
+    OutOfBounds {
+        span: Span,
+        cell: isize,
+        tape_len: usize,
+    },
+    /// `cell` went below 0, or past its largest value if not `below_zero`.
+    Overflow {
+        span: Span,
+        cell: isize,
+        below_zero: bool,
+    },
+    /// A growable tape couldn't grow as far as `cell`, as happens when the
+    /// JIT runs out of the memory it set aside for the tape.
+    TapeFull { span: Span, cell: isize },
+}
+
+impl RuntimeError {
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::OutOfBounds { span, .. }
+            | RuntimeError::Overflow { span, .. }
+            | RuntimeError::TapeFull { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::OutOfBounds {
+                span,
+                cell,
+                tape_len,
+            } => write!(
+                f,
+                "Pointer out of bounds at {}: cell {} is not on the tape of {} cells",
+                span, cell, tape_len
+            ),
+            RuntimeError::Overflow {
+                span,
+                cell,
+                below_zero,
+            } => write!(
+                f,
+                "Cell overflow at {}: cell {} went {}",
+                span,
+                cell,
+                if *below_zero {
+                    "below 0"
+                } else {
+                    "past its largest value"
+                }
+            ),
+            RuntimeError::TapeFull { span, cell } => write!(
+                f,
+                "Tape full at {}: cell {} is further than the tape can grow",
+                span, cell
+            ),
+        }
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+/// A program can't be turned into the requested target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodegenError {
+    /// The backend can't honour an option, such as unbounded cells
+    Unsupported(String),
+    /// The generated code couldn't be assembled
+    Assembler(String),
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::Unsupported(what) => write!(f, "{}", what),
+            CodegenError::Assembler(why) => write!(f, "Assembler failed: {}", why),
+        }
+    }
+}
+
+impl error::Error for CodegenError {}
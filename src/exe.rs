@@ -1,121 +1,398 @@
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Stdin, Stdout, Write};
 
-use crate::{ir::Instruction, lex::{Token, TokenType}};
+use crate::{
+    bytecode::{self, Bytecode},
+    config::{BoundsPolicy, Config, EofPolicy, OverflowPolicy},
+    error::{Error, RuntimeError},
+    ir::{Instruction, Representation},
+    lex::{Span, Token, TokenType},
+    tape::Tape,
+};
 
-pub struct Runner {
+/// Interpreter state: the tape, the pointer, the code being run and the
+/// streams `,` and `.` talk to.
+///
+/// Output is buffered and flushed whenever the program waits for input and
+/// when a run finishes. Input is read one byte at a time as the program asks
+/// for it, so wrap slow readers in a `BufReader` yourself.
+pub struct Runner<R = Stdin, W = Stdout>
+where
+    W: Write,
+{
     ins: usize,
     ptr: usize,
+    // Where cell 0 of the original tape is, once it has grown to the left
+    origin: usize,
     tokens: Vec<Token>,
-    stack: Vec<(usize, usize)>,
-    tape: [u8; 30000],
+    jumps: Vec<usize>,
+    tape: Tape,
+    config: Config,
+    input: R,
+    output: BufWriter<W>,
 }
 
 impl Runner {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    /// A runner on stdin and stdout.
+    ///
+    /// `jumps` is the bracket table `SyntaxParser` built for `tokens`.
+    pub fn new(tokens: Vec<Token>, jumps: Vec<usize>) -> Self {
+        Self::with_io(tokens, jumps, io::stdin(), io::stdout())
+    }
+}
+
+impl<R: Read, W: Write> Runner<R, W> {
+    /// A runner reading `,` from `input` and writing `.` to `output`.
+    pub fn with_io(tokens: Vec<Token>, jumps: Vec<usize>, input: R, output: W) -> Self {
         Self {
             ins: 0,
             ptr: 0,
+            origin: 0,
             tokens,
-            stack: Vec::new(),
-            tape: [0u8; 30000],
+            jumps,
+            tape: Tape::new(Config::default().tape_len, Config::default().cell),
+            config: Config::default(),
+            input,
+            output: BufWriter::new(output),
+        }
+    }
+
+    /// Sets the options to run with. This clears the tape.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.tape = Tape::new(config.tape_len, config.cell);
+        self.ptr = 0;
+        self.origin = 0;
+        self.config = config;
+        self
+    }
+
+    /// Runs the tokens from where the last run stopped.
+    ///
+    /// A runtime error abandons the rest of the tokens, so code added later
+    /// starts from a clean slate.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let result = self.exec();
+        if result.is_err() {
+            self.ins = self.tokens.len();
         }
+        let flushed = self.output.flush().map_err(Error::from);
+        result.and(flushed)
     }
 
-    pub fn run(&mut self) {
+    fn exec(&mut self) -> Result<(), Error> {
         while self.ins < self.tokens.len() {
-            let tk = &self.tokens[self.ins];
+            let tk = self.tokens[self.ins];
             match tk.token_type() {
                 TokenType::Increment => {
-                    self.tape[self.ptr] = self.tape[self.ptr].wrapping_add(1);
+                    self.add_to(self.ptr, 1, tk.span())?;
                 }
                 TokenType::Decrement => {
-                    self.tape[self.ptr] = self.tape[self.ptr].wrapping_sub(1);
+                    self.add_to(self.ptr, -1, tk.span())?;
                 }
                 TokenType::MoveRight => {
-                    self.ptr = (self.ptr + 1) % 30000;
+                    self.ptr = self.cell(1, tk.span())?;
                 }
                 TokenType::MoveLeft => {
-                    self.ptr = (self.ptr + 30000 - 1) % 30000;
+                    self.ptr = self.cell(-1, tk.span())?;
                 }
-                TokenType::LoopStart => {
-                    self.stack.push((self.ptr, self.ins));
+                TokenType::LoopStart if self.tape.is_zero(self.ptr) => {
+                    self.ins = self.jumps[self.ins];
                 }
-                TokenType::LoopEnd => {
-                    if self.tape[self.stack.last().unwrap().0] == 0 {
-                        self.stack.pop();
-                    } else {
-                        self.ins = self.stack.last().unwrap().1;
-                    }
+                TokenType::LoopEnd if !self.tape.is_zero(self.ptr) => {
+                    self.ins = self.jumps[self.ins];
                 }
                 TokenType::Print => {
-                    print!("{}", String::from_utf8_lossy(&[self.tape[self.ptr]]));
+                    self.output.write_all(&[self.tape.byte(self.ptr)])?;
                 }
                 TokenType::Read => {
-                    let mut buffer = [0; 1];
-                    std::io::stdin().read_exact(&mut buffer).unwrap();
-                    self.tape[self.ptr] = buffer[0];
+                    self.read(self.ptr)?;
                 }
                 _ => {}
             }
             self.ins += 1;
         }
+
+        Ok(())
     }
 
-    // Try to implement the loop handling and rest of it is already done.
-    pub fn run_ins(&mut self, ins: &Vec<Instruction>){
+    /// Executes a lowered program against the tape.
+    ///
+    /// Matching loop boundaries are resolved once up front, so a jump is a
+    /// single index lookup instead of a rescan of the instruction list.
+    pub fn run_ins(&mut self, rep: &Representation) -> Result<(), Error> {
+        let result = self.exec_ins(rep);
+        let flushed = self.output.flush().map_err(Error::from);
+        result.and(flushed)
+    }
+
+    fn exec_ins(&mut self, rep: &Representation) -> Result<(), Error> {
+        let ins = &rep.instructions;
+        let jumps = jump_table(ins);
+
         let mut i = 0;
         while i < ins.len() {
-            let is = &ins[i];
-            match is {
-                Instruction::Print(ptr) => {
-                    print!("{}", self.tape[*ptr]);
-                }
-                Instruction::Sum(val, ptr) => {
-                    self.tape[*ptr] = self.tape[*ptr].wrapping_add(*val as u8);
-                }
-                Instruction::Read(ptr) => {
-                    let mut buffer = [0; 1];
-                    std::io::stdin().read_exact(&mut buffer).unwrap();
-                    self.tape[*ptr] = buffer[0];
-                }
-                Instruction::LoopStart(ptr) => {
-                    if self.tape[*ptr] == 0 {
-                        let mut count = 1;
-                        while count != 0 {
-                            i += 1;
-                            match ins[i] {
-                                Instruction::LoopStart(_) => count += 1,
-                                Instruction::LoopEnd(_) => count -= 1,
-                                _ => {}
-                            }
-                        }
+            match ins[i] {
+                Instruction::Print { offset } => {
+                    let cell = self.cell(offset, rep.spans[i])?;
+                    self.output.write_all(&[self.tape.byte(cell)])?;
+                }
+                Instruction::Add { offset, delta } => {
+                    let cell = self.cell(offset, rep.spans[i])?;
+                    self.add_to(cell, delta, rep.spans[i])?;
+                }
+                Instruction::Move(n) => {
+                    self.ptr = self.cell(n, rep.spans[i])?;
+                }
+                Instruction::Read { offset } => {
+                    let cell = self.cell(offset, rep.spans[i])?;
+                    self.read(cell)?;
+                }
+                Instruction::LoopStart if self.tape.is_zero(self.ptr) => {
+                    i = jumps[i];
+                }
+                Instruction::LoopEnd if !self.tape.is_zero(self.ptr) => {
+                    i = jumps[i];
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Executes bytecode against the tape.
+    ///
+    /// Jumps are already resolved in the code and operands sit right after
+    /// their opcode, so this is the fastest of the interpreters.
+    pub fn run_bytecode(&mut self, code: &Bytecode) -> Result<(), Error> {
+        let result = self.exec_bytecode(code);
+        let flushed = self.output.flush().map_err(Error::from);
+        result.and(flushed)
+    }
+
+    fn exec_bytecode(&mut self, bytecode: &Bytecode) -> Result<(), Error> {
+        let code = bytecode.code();
+
+        let mut pc = 0;
+        while pc < code.len() {
+            pc = self.exec_bounded(code, pc)?;
+            if pc < code.len() {
+                pc = self.step_bytecode(bytecode, pc)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs bytecode from `pc` straight on the cells of a bounded tape for as
+    // long as nothing needs the policies: an address off the tape, a cell
+    // leaving its range with the trapping overflow policy, or a read.
+    // Returns where it stopped, for `step_bytecode` to take over.
+    fn exec_bounded(&mut self, code: &[i32], mut pc: usize) -> Result<usize, Error> {
+        let Some((cells, mask)) = self.tape.bounded_mut() else {
+            return Ok(pc);
+        };
+        let mask = mask as i64;
+        let trap = self.config.overflow == OverflowPolicy::Trap;
+        let mut ptr = self.ptr;
+        // Negative offsets wrap round to huge indices, which are off the tape.
+        let at = |ptr: usize, offset: i32| ptr.wrapping_add(offset as usize);
+
+        while pc < code.len() {
+            match code[pc] {
+                bytecode::ADD => {
+                    let Some(cell) = cells.get_mut(at(ptr, code[pc + 1])) else {
+                        break;
+                    };
+                    let sum = *cell as i64 + code[pc + 2] as i64;
+                    if trap && sum & !mask != 0 {
+                        break;
                     }
+                    *cell = (sum & mask) as u32;
+                    pc += 3;
                 }
-                Instruction::LoopEnd(ptr) => {
-                    if self.tape[*ptr] != 0 {
-                        let mut count = 1;
-                        while count != 0 {
-                            i -= 1;
-                            match ins[i] {
-                                Instruction::LoopStart(_) => count -= 1,
-                                Instruction::LoopEnd(_) => count += 1,
-                                _ => {}
-                            }
-                        }
+                bytecode::MOVE => {
+                    let target = at(ptr, code[pc + 1]);
+                    if target >= cells.len() {
+                        break;
                     }
+                    ptr = target;
+                    pc += 2;
                 }
-                _ => {}
+                bytecode::PRINT => {
+                    let Some(cell) = cells.get(at(ptr, code[pc + 1])) else {
+                        break;
+                    };
+                    if let Err(e) = self.output.write_all(&[*cell as u8]) {
+                        self.ptr = ptr;
+                        return Err(e.into());
+                    }
+                    pc += 2;
+                }
+                bytecode::JZ if cells[ptr] == 0 => pc = code[pc + 1] as usize,
+                bytecode::JNZ if cells[ptr] != 0 => pc = code[pc + 1] as usize,
+                bytecode::JZ | bytecode::JNZ => pc += 2,
+                _ => break,
+            }
+        }
+
+        self.ptr = ptr;
+        Ok(pc)
+    }
+
+    // Runs the instruction at `pc` with the policies in the config, returning
+    // where to go next.
+    fn step_bytecode(&mut self, bytecode: &Bytecode, pc: usize) -> Result<usize, Error> {
+        let code = bytecode.code();
+        let next = match code[pc] {
+            bytecode::ADD => {
+                let cell = self.cell_at(code[pc + 1], bytecode, pc)?;
+                self.add_to(cell, code[pc + 2], bytecode.span_at(pc))?;
+                pc + 3
+            }
+            bytecode::MOVE => {
+                self.ptr = self.cell_at(code[pc + 1], bytecode, pc)?;
+                pc + 2
+            }
+            bytecode::PRINT => {
+                let cell = self.cell_at(code[pc + 1], bytecode, pc)?;
+                self.output.write_all(&[self.tape.byte(cell)])?;
+                pc + 2
             }
+            bytecode::READ => {
+                let cell = self.cell_at(code[pc + 1], bytecode, pc)?;
+                self.read(cell)?;
+                pc + 2
+            }
+            bytecode::JZ if self.tape.is_zero(self.ptr) => code[pc + 1] as usize,
+            bytecode::JNZ if !self.tape.is_zero(self.ptr) => code[pc + 1] as usize,
+            _ => pc + 2,
+        };
+        Ok(next)
+    }
+
+    // Same as `cell`, looking up the span of the instruction at `pc` only if
+    // the offset leaves the tape.
+    #[inline]
+    fn cell_at(&mut self, offset: i32, code: &Bytecode, pc: usize) -> Result<usize, Error> {
+        let target = self.ptr as isize + offset as isize;
+        if (0..self.tape.len() as isize).contains(&target) {
+            return Ok(target as usize);
+        }
+        self.cell(offset as isize, code.span_at(pc))
+    }
+
+    // Adds `delta` to `cell`. If that wraps around and the overflow policy
+    // says to trap, `span` is the code to blame.
+    fn add_to(&mut self, cell: usize, delta: i32, span: Span) -> Result<(), Error> {
+        if self.tape.add(cell, delta) || self.config.overflow == OverflowPolicy::Wrap {
+            return Ok(());
         }
+        Err(self.overflow(cell, delta, span))
     }
 
-    pub fn add(&mut self, tokens: &mut Vec<Token>) {
+    // The error for `delta` taking `cell` out of range at `span`.
+    fn overflow(&self, cell: usize, delta: i32, span: Span) -> Error {
+        RuntimeError::Overflow {
+            span,
+            cell: cell as isize - self.origin as isize,
+            below_zero: delta < 0,
+        }
+        .into()
+    }
+
+    // Reads a byte into `cell` for a `,`, making sure any prompt printed so
+    // far is out. At the end of the input the EOF policy decides.
+    fn read(&mut self, cell: usize) -> Result<(), Error> {
+        self.output.flush()?;
+
+        let mut buffer = [0; 1];
+        loop {
+            match self.input.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(_) => {
+                    self.tape.set(cell, buffer[0]);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match self.config.eof {
+            EofPolicy::Unchanged => {}
+            EofPolicy::Zero => self.tape.set(cell, 0),
+            EofPolicy::Max => self.tape.set_max(cell),
+        }
+        Ok(())
+    }
+
+    // Index of the cell `offset` cells away from the pointer. If that is off
+    // either end of the tape, the bounds policy decides; `span` is the code
+    // to blame should it say to trap.
+    fn cell(&mut self, offset: isize, span: Span) -> Result<usize, Error> {
+        let target = self.ptr as isize + offset;
+        let len = self.tape.len();
+        if (0..len as isize).contains(&target) {
+            return Ok(target as usize);
+        }
+
+        match self.config.bounds {
+            BoundsPolicy::Wrap => Ok(target.rem_euclid(len as isize) as usize),
+            BoundsPolicy::Trap => Err(RuntimeError::OutOfBounds {
+                span,
+                cell: target - self.origin as isize,
+                tape_len: len,
+            }
+            .into()),
+            BoundsPolicy::Grow if target < 0 => {
+                let extra = self.tape.grow_left(target.unsigned_abs());
+                self.ptr += extra;
+                self.origin += extra;
+                Ok((target + extra as isize) as usize)
+            }
+            BoundsPolicy::Grow => {
+                self.tape.grow_right(target as usize + 1);
+                Ok(target as usize)
+            }
+        }
+    }
+
+    /// Appends more code to run, along with its own bracket table.
+    pub fn add(&mut self, tokens: &mut Vec<Token>, jumps: &[usize]) {
+        let base = self.tokens.len();
+        self.jumps.extend(jumps.iter().map(|j| j + base));
         self.tokens.append(tokens);
     }
 
     pub fn print_tape(&self, val: usize) {
-        for i in 0..val {
-            print!("{} ", self.tape[i]);
+        for i in self.origin..(self.origin + val).min(self.tape.len()) {
+            print!("{} ", self.tape.value(i));
         }
     }
+
+    /// Flushes and hands back the output stream.
+    pub fn into_output(self) -> io::Result<W> {
+        self.output.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+// For every loop boundary, the index of its matching counterpart.
+fn jump_table(ins: &[Instruction]) -> Vec<usize> {
+    let mut jumps = vec![0; ins.len()];
+    let mut stack = Vec::new();
+
+    for (i, is) in ins.iter().enumerate() {
+        match is {
+            Instruction::LoopStart => stack.push(i),
+            Instruction::LoopEnd => {
+                let start = stack.pop().expect("unbalanced loops in IR");
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => {}
+        }
+    }
+
+    jumps
 }
@@ -1,83 +1,482 @@
-use crate::ir::Instruction;
+use crate::{
+    asm::Dialect,
+    config::{BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy, Target},
+    error::CodegenError,
+    ir::{Instruction, Loops, Representation},
+    lex::Span,
+    x86::{Alu, Assembly, Cond, Line, Mem, Op, Operand, Reg, Size, ENTRY},
+};
 
+// Size of the mapping behind a growable tape.
+const GROWABLE_TAPE_BYTES: i64 = 0x4000_0000;
+
+/// Generates FASM source for x86 Linux from the IR, 32 or 64-bit depending
+/// on [`Config::target`].
 pub struct FasmGenerator {
-    instructions: Vec<String>,
-    loop_count: usize,
-    loop_stack: Vec<usize>, // new stack to track loop IDs
+    lines: Vec<Line>,
+    loops: Loops,
+    read_count: usize,
+    wrap_count: usize,
+    trap_messages: Vec<(String, Vec<u8>)>, // one per check that can stop the program
+    config: Config,
+}
+
+impl Default for FasmGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FasmGenerator {
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
         Self {
-            instructions: vec![
-                "format ELF executable 3".to_string(),
-                "entry start".to_string(),
-                "".to_string(),
-                "segment readable writeable".to_string(),
-                "tape rb 30000".to_string(), // Reserve 30000 bytes for brainfuck tape
-                "".to_string(),
-                "segment readable executable".to_string(),
-                "start:".to_string(),
-                "    mov ebp, tape".to_string(), // Base pointer for tape
-            ],
-            loop_count: 0,
-            loop_stack: Vec::new(),
+            lines: vec![],
+            loops: Loops::new(),
+            read_count: 0,
+            wrap_count: 0,
+            trap_messages: Vec::new(),
+            config,
         }
     }
 
-    pub fn generate(&mut self, ir: &[Instruction]) -> String {
-        for ins in ir {
+    pub fn generate(&mut self, rep: &Representation) -> Result<String, CodegenError> {
+        Ok(Dialect::Fasm.render(&self.lower(rep)?))
+    }
+
+    /// Lowers the IR to x86 instructions, which [`Dialect::render`] prints
+    /// and [`elf::executable`](crate::elf::executable) encodes.
+    pub fn lower(&mut self, rep: &Representation) -> Result<Assembly, CodegenError> {
+        let size = match self.config.cell {
+            CellWidth::U8 => Size::Byte,
+            CellWidth::U16 => Size::Word,
+            CellWidth::U32 => Size::Dword,
+            CellWidth::Unbounded => {
+                return Err(CodegenError::Unsupported(
+                    "Unbounded cells are not supported by the x86 backend".to_string(),
+                ))
+            }
+        };
+        let bytes = size.bytes() as isize;
+
+        let mut bss = Vec::new();
+        if self.config.bounds != BoundsPolicy::Grow {
+            // Reserve the cells for brainfuck tape
+            bss.push(("tape".to_string(), size, self.config.tape_len.max(1)));
+        } else {
+            // Where the mapping behind the tape starts and ends
+            bss.push(("tape_start".to_string(), self.word(), 1));
+            bss.push(("tape_end".to_string(), self.word(), 1));
+        }
+        if bytes > 1 {
+            bss.push(("inbuf".to_string(), Size::Byte, 1)); // Wider cells are read through here
+        }
+
+        self.label(ENTRY);
+        if self.config.bounds == BoundsPolicy::Grow {
+            self.map_tape();
+        } else {
+            // RIP-relative on x86-64
+            self.note(Op::Lea(Reg::Rbp, label("tape")), "Current cell pointer");
+        }
+
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
             match ins {
-                Instruction::Sum(val, ptr) => {
-                    // Add/subtract value to/from memory at tape[ptr]
-                    self.instructions.push(format!("    add byte [ebp + {}], {}", ptr, val));
-                }
-                Instruction::Print(ptr) => {
-                    self.instructions.push(format!("; Print character at position {}", ptr));
-                    self.instructions.push("    mov eax, 4         ; sys_write syscall number".to_string());
-                    self.instructions.push("    mov ebx, 1         ; file descriptor (stdout)".to_string());
-                    self.instructions.push(format!("    lea ecx, [ebp + {}]  ; pointer to character", ptr));
-                    self.instructions.push("    mov edx, 1         ; number of bytes to write".to_string());
-                    self.instructions.push("    int 0x80           ; invoke syscall".to_string());
+                Instruction::Add { offset, delta } => {
+                    let addr = self.checked_addr(*offset, *span);
+                    if self.config.overflow == OverflowPolicy::Trap {
+                        self.checked_add(addr, *delta, *span);
+                        continue;
+                    }
+                    // Add/subtract value to/from memory at tape[ptr + offset]
+                    let value = match bytes {
+                        1 => *delta as u8 as i64,
+                        2 => *delta as u16 as i64,
+                        _ => *delta as u32 as i64,
+                    };
+                    self.op(Op::Alu(Alu::Add, size, addr.into(), value.into()));
                 }
-                Instruction::Read(ptr) => {
-                    self.instructions.push(format!("; Read character into position {}", ptr));
-                    self.instructions.push("    mov eax, 3         ; sys_read syscall number".to_string());
-                    self.instructions.push("    mov ebx, 0         ; file descriptor (stdin)".to_string());
-                    self.instructions.push(format!("    lea ecx, [ebp + {}]  ; buffer to read into", ptr));
-                    self.instructions.push("    mov edx, 1         ; number of bytes to read".to_string());
-                    self.instructions.push("    int 0x80           ; invoke syscall".to_string());
+                Instruction::Move(n) => {
+                    self.op(Op::Lea(self.scratch(), self.addr(*n)));
+                    self.check_addr(*n, *span);
+                    self.op(Op::Mov(self.word(), Reg::Rbp.into(), self.scratch().into()));
                 }
-                Instruction::LoopStart(ptr) => {
-                    let loop_id = self.loop_count;
-                    self.loop_count += 1;
-                    self.loop_stack.push(loop_id);
-                    self.instructions.push(format!("; Start of loop {}", loop_id));
-                    self.instructions.push(format!("loop_start_{}:", loop_id));
-                    self.instructions.push(format!("    cmp byte [ebp + {}], 0", ptr));
-                    self.instructions.push(format!("    je loop_end_{}", loop_id));
+                Instruction::Print { offset } => {
+                    self.comment(format!("Print character at offset {}", offset));
+                    let addr = self.checked_addr(*offset, *span);
+                    self.syscall(
+                        Syscall::Write,
+                        vec![
+                            (1.into(), "file descriptor (stdout)"),
+                            (addr.into(), "pointer to character"),
+                            (1.into(), "number of bytes to write"),
+                        ],
+                    );
                 }
-                Instruction::LoopEnd(ptr) => {
-                    // Pop the matching loop id from the stack
-                    if let Some(loop_id) = self.loop_stack.pop() {
-                        self.instructions.push(format!("; End of loop {}", loop_id));
-                        self.instructions.push(format!("    cmp byte [ebp + {}], 0", ptr));
-                        self.instructions.push(format!("    jne loop_start_{}", loop_id));
-                        self.instructions.push(format!("loop_end_{}:", loop_id));
+                Instruction::Read { offset } => {
+                    self.comment(format!("Read character into offset {}", offset));
+                    let addr = self.checked_addr(*offset, *span);
+                    let buffer = if bytes == 1 {
+                        addr.clone()
                     } else {
-                        // Should not happen if the parser checked the syntax correctly.
-                        panic!("Unmatched loop end encountered during assembly generation.");
-                    }
+                        label("inbuf")
+                    };
+                    self.syscall(
+                        Syscall::Read,
+                        vec![
+                            (0.into(), "file descriptor (stdin)"),
+                            (buffer.into(), "buffer to read into"),
+                            (1.into(), "number of bytes to read"),
+                        ],
+                    );
+                    self.store_input(addr, size);
+                }
+                Instruction::LoopStart => {
+                    let loop_id = self.loops.start();
+                    self.comment(format!("Start of loop {}", loop_id));
+                    self.label(&format!("loop_start_{}", loop_id));
+                    self.op(Op::Alu(Alu::Cmp, size, self.addr(0).into(), 0.into()));
+                    self.op(Op::J(Cond::E, format!("loop_end_{}", loop_id)));
+                }
+                Instruction::LoopEnd => {
+                    let loop_id = self.loops.end();
+                    self.comment(format!("End of loop {}", loop_id));
+                    self.op(Op::Alu(Alu::Cmp, size, self.addr(0).into(), 0.into()));
+                    self.op(Op::J(Cond::Ne, format!("loop_start_{}", loop_id)));
+                    self.label(&format!("loop_end_{}", loop_id));
                 }
             }
         }
 
         // Add exit syscall
-        self.instructions.push("\n; Exit program".to_string());
-        self.instructions.push("    mov eax, 1      ; sys_exit syscall number".to_string());
-        self.instructions.push("    xor ebx, ebx    ; exit status 0".to_string());
-        self.instructions.push("    int 0x80        ; invoke syscall".to_string());
+        self.lines.push(Line::Blank);
+        self.comment("Exit program".to_string());
+        self.syscall(Syscall::Exit, vec![(0.into(), "exit status 0")]);
+
+        self.routines(bytes);
+
+        Ok(Assembly {
+            target: self.config.target,
+            bss,
+            text: std::mem::take(&mut self.lines),
+            rodata: std::mem::take(&mut self.trap_messages),
+        })
+    }
+
+    // A growable tape is a big mapping the kernel only backs with memory once
+    // it is touched, with the pointer starting in the middle so it can grow
+    // either way until it reaches an end.
+    fn map_tape(&mut self) {
+        self.comment("Map the tape".to_string());
+        self.syscall(
+            Syscall::Mmap,
+            vec![
+                (0.into(), "anywhere"),
+                (GROWABLE_TAPE_BYTES.into(), "length"),
+                (3.into(), "PROT_READ | PROT_WRITE"),
+                (0x4022.into(), "MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE"),
+                ((-1).into(), "no file"),
+                (0.into(), "offset 0"),
+            ],
+        );
+        let word = self.word();
+        self.note(
+            Op::Alu(Alu::Cmp, word, Reg::Rax.into(), (-4096).into()),
+            "errors are -4095..-1",
+        );
+        self.op(Op::J(Cond::Be, "tape_mapped".to_string()));
+        self.syscall(Syscall::Exit, vec![(1.into(), "exit status 1")]);
+        self.label("tape_mapped");
+        let middle = Mem::Base(Reg::Rax, (GROWABLE_TAPE_BYTES / 2) as i32);
+        self.note(Op::Lea(Reg::Rbp, middle), "Current cell pointer");
+        self.op(Op::Mov(word, label("tape_start").into(), Reg::Rax.into()));
+        self.op(Op::Alu(
+            Alu::Add,
+            word,
+            Reg::Rax.into(),
+            GROWABLE_TAPE_BYTES.into(),
+        ));
+        self.op(Op::Mov(word, label("tape_end").into(), Reg::Rax.into()));
+    }
+
+    // Address of the cell `offset` cells away from the current one, brought
+    // back on the tape as the bounds policy says. The pointer itself never
+    // leaves the tape, so only cells at an offset need checking.
+    fn checked_addr(&mut self, offset: isize, span: Span) -> Mem {
+        if offset == 0 {
+            return self.addr(offset);
+        }
+        self.op(Op::Lea(self.scratch(), self.addr(offset)));
+        self.check_addr(offset, span);
+        Mem::Base(self.scratch(), 0)
+    }
+
+    // Wraps or checks the address `offset` cells away in the scratch
+    // register; `span` is the code to blame if it is off the tape, or off
+    // the mapping a growable tape lives in.
+    fn check_addr(&mut self, offset: isize, span: Span) {
+        let message = match self.config.bounds {
+            BoundsPolicy::Wrap => return self.wrap_addr(offset),
+            BoundsPolicy::Trap => format!("Pointer out of bounds at {}", span),
+            BoundsPolicy::Grow => format!("Tape full at {}", span),
+        };
+        self.trap_message(message);
+        self.op(Op::Call("check_bounds".to_string()));
+    }
+
+    // Brings the address in the scratch register back on the tape. The
+    // pointer is always on it, so only the end `offset` heads for can have
+    // been passed, and the call is skipped unless it was.
+    fn wrap_addr(&mut self, offset: isize) {
+        let (word, scratch) = (self.word(), self.scratch());
+        let done = format!("wrapped_{}", self.wrap_count);
+        self.wrap_count += 1;
+
+        self.op(Op::Lea(Reg::Rax, label("tape")));
+        let on_tape = if offset < 0 {
+            Cond::Ae
+        } else {
+            let bytes = self.config.cell.bytes().unwrap_or(1);
+            let len = (self.config.tape_len.max(1) * bytes) as i64;
+            self.op(Op::Alu(Alu::Add, word, Reg::Rax.into(), len.into()));
+            Cond::B
+        };
+        self.op(Op::Alu(Alu::Cmp, word, scratch.into(), Reg::Rax.into()));
+        self.op(Op::J(on_tape, done.clone()));
+        self.op(Op::Call("wrap_addr".to_string()));
+        self.label(&done);
+    }
+
+    // Adds `delta` to the cell at `addr`, stopping the program if it goes
+    // below 0 or past its largest value; `span` is the code to blame.
+    fn checked_add(&mut self, addr: Mem, delta: i32, span: Span) {
+        let direction = if delta < 0 {
+            "below 0"
+        } else {
+            "past its largest value"
+        };
+        self.trap_message(format!(
+            "Cell overflow at {}: cell went {}",
+            span, direction
+        ));
+
+        let (size, max) = match self.config.cell {
+            CellWidth::U8 => (Size::Byte, u8::MAX as u32),
+            CellWidth::U16 => (Size::Word, u16::MAX as u32),
+            _ => (Size::Dword, u32::MAX),
+        };
+        if delta.unsigned_abs() > max {
+            self.note(Op::Jmp("trap".to_string()), "always overflows");
+            return;
+        }
+        // The carry flag is set exactly when the result doesn't fit.
+        let alu = if delta < 0 { Alu::Sub } else { Alu::Add };
+        let value = delta.unsigned_abs() as i64;
+        self.op(Op::Alu(alu, size, addr.into(), value.into()));
+        self.op(Op::J(Cond::C, "trap".to_string()));
+    }
+
+    // Loads the message `trap` prints should the check that follows fail.
+    fn trap_message(&mut self, message: String) {
+        let id = self.trap_messages.len();
+        let label = format!("trap_msg_{}", id);
+        let text = format!("Runtime error: {}\n", message).into_bytes();
+        let len = text.len() as i64;
+        self.trap_messages.push((label.clone(), text));
+
+        let regs = self.arg_regs();
+        self.op(Op::Lea(regs[1], Mem::Label(label)));
+        self.op(Op::Mov(self.word(), regs[2].into(), len.into()));
+    }
+
+    // The routines behind `check_addr` and `trap`.
+    fn routines(&mut self, bytes: isize) {
+        let len = (self.config.tape_len.max(1) as isize * bytes) as i64;
+        let (word, scratch) = (self.word(), self.scratch());
+        // Whether the scratch register is off either end of the tape
+        let check = |this: &mut Self, below: &str, above: Cond, past: &str| {
+            this.op(Op::Lea(Reg::Rax, label("tape")));
+            this.op(Op::Alu(Alu::Cmp, word, scratch.into(), Reg::Rax.into()));
+            this.op(Op::J(Cond::B, below.to_string()));
+            this.op(Op::Alu(Alu::Add, word, Reg::Rax.into(), len.into()));
+            this.op(Op::Alu(Alu::Cmp, word, scratch.into(), Reg::Rax.into()));
+            this.op(Op::J(above, past.to_string()));
+        };
+        match self.config.bounds {
+            BoundsPolicy::Wrap => {
+                self.lines.push(Line::Blank);
+                self.comment(format!(
+                    "Bring {} back on the tape from either side",
+                    scratch.name(word)
+                ));
+                self.label("wrap_addr");
+                check(self, "wrap_below", Cond::B, "wrap_done");
+                self.op(Op::Alu(Alu::Sub, word, scratch.into(), len.into()));
+                self.op(Op::Jmp("wrap_addr".to_string()));
+                self.label("wrap_below");
+                self.op(Op::Alu(Alu::Add, word, scratch.into(), len.into()));
+                self.op(Op::Jmp("wrap_addr".to_string()));
+                self.label("wrap_done");
+                self.op(Op::Ret);
+            }
+            // Every call comes with a trap message, so none means no calls.
+            BoundsPolicy::Trap if !self.trap_messages.is_empty() => {
+                self.lines.push(Line::Blank);
+                self.comment(format!(
+                    "Stop with the trap message if {} is off the tape",
+                    scratch.name(word)
+                ));
+                self.label("check_bounds");
+                check(self, "trap", Cond::Ae, "trap");
+                self.op(Op::Ret);
+            }
+            BoundsPolicy::Grow if !self.trap_messages.is_empty() => {
+                self.lines.push(Line::Blank);
+                self.comment(format!(
+                    "Stop with the trap message if {} is off the mapping",
+                    scratch.name(word)
+                ));
+                self.label("check_bounds");
+                let (start, end) = (label("tape_start"), label("tape_end"));
+                self.op(Op::Alu(Alu::Cmp, word, scratch.into(), start.into()));
+                self.op(Op::J(Cond::B, "trap".to_string()));
+                self.op(Op::Alu(Alu::Cmp, word, scratch.into(), end.into()));
+                self.op(Op::J(Cond::Ae, "trap".to_string()));
+                self.op(Op::Ret);
+            }
+            BoundsPolicy::Trap | BoundsPolicy::Grow => {}
+        }
 
-        self.instructions.join("\n")
+        if self.trap_messages.is_empty() {
+            return;
+        }
+        let regs = self.arg_regs();
+        self.lines.push(Line::Blank);
+        self.comment(format!(
+            "Print the message in {}/{} and give up",
+            regs[1].name(word),
+            regs[2].name(word)
+        ));
+        self.label("trap");
+        self.syscall(Syscall::Write, vec![(2.into(), "file descriptor (stderr)")]);
+        self.syscall(Syscall::Exit, vec![(1.into(), "exit status 1")]);
     }
-}
\ No newline at end of file
+
+    // Moves the byte just read into the cell at `addr`, or applies the EOF
+    // policy if there was none; eax holds the number of bytes read.
+    fn store_input(&mut self, addr: Mem, size: Size) {
+        let wide = self.config.cell != CellWidth::U8;
+        if !wide && self.config.eof == EofPolicy::Unchanged {
+            return; // the byte already landed in the cell
+        }
+
+        let read_id = self.read_count;
+        self.read_count += 1;
+        self.op(Op::Alu(Alu::Cmp, Size::Dword, Reg::Rax.into(), 1.into()));
+        self.op(Op::J(Cond::Ne, format!("read_eof_{}", read_id)));
+        if wide {
+            self.op(Op::Movzx(Reg::Rax, label("inbuf")));
+            self.op(Op::Mov(size, addr.clone().into(), Reg::Rax.into()));
+        }
+        self.op(Op::Jmp(format!("read_done_{}", read_id)));
+        self.label(&format!("read_eof_{}", read_id));
+        match self.config.eof {
+            EofPolicy::Unchanged => {}
+            EofPolicy::Zero => self.op(Op::Mov(size, addr.into(), 0.into())),
+            EofPolicy::Max => self.op(Op::Mov(size, addr.into(), (-1).into())),
+        }
+        self.label(&format!("read_done_{}", read_id));
+    }
+
+    // Memory operand for the cell `offset` cells away from the current one.
+    fn addr(&self, offset: isize) -> Mem {
+        let bytes = self.config.cell.bytes().unwrap_or(1) as isize;
+        Mem::Base(Reg::Rbp, (offset * bytes) as i32)
+    }
+
+    // Asks the kernel for `call`, passing `args` in the registers the target
+    // takes arguments in. Arguments already in place can be left out at the
+    // end, and memory operands pass their address.
+    fn syscall(&mut self, call: Syscall, args: Vec<(Operand, &str)>) {
+        let x86_64 = self.config.target == Target::X86_64;
+        let (number, name) = match (call, x86_64) {
+            (Syscall::Read, false) => (3, "sys_read"),
+            (Syscall::Write, false) => (4, "sys_write"),
+            (Syscall::Exit, false) => (1, "sys_exit"),
+            (Syscall::Mmap, false) => (192, "sys_mmap2"),
+            (Syscall::Read, true) => (0, "sys_read"),
+            (Syscall::Write, true) => (1, "sys_write"),
+            (Syscall::Exit, true) => (60, "sys_exit"),
+            (Syscall::Mmap, true) => (9, "sys_mmap"),
+        };
+
+        let word = self.word();
+        self.note(
+            Op::Mov(word, Reg::Rax.into(), number.into()),
+            &format!("{} syscall number", name),
+        );
+        for ((value, comment), reg) in args.into_iter().zip(self.arg_regs()) {
+            match value {
+                Operand::Mem(mem) => self.note(Op::Lea(reg, mem), comment),
+                value => self.note(Op::Mov(word, reg.into(), value), comment),
+            }
+        }
+        let op = if x86_64 { Op::Syscall } else { Op::Int(0x80) };
+        self.note(op, "invoke syscall");
+    }
+
+    // Holds addresses being checked against the tape; never a syscall
+    // argument that comes before a memory operand. The current cell's
+    // address is in rbp, and rax is free for the bounds routines.
+    fn scratch(&self) -> Reg {
+        match self.config.target {
+            Target::X86 => Reg::Rdi,
+            Target::X86_64 => Reg::Rbx,
+        }
+    }
+
+    // Where syscall arguments go, in order.
+    fn arg_regs(&self) -> [Reg; 6] {
+        match self.config.target {
+            Target::X86 => [Reg::Rbx, Reg::Rcx, Reg::Rdx, Reg::Rsi, Reg::Rdi, Reg::Rbp],
+            Target::X86_64 => [Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::R10, Reg::R8, Reg::R9],
+        }
+    }
+
+    // The size of addresses, and so of the registers holding them.
+    fn word(&self) -> Size {
+        Size::of_addresses(self.config.target)
+    }
+
+    fn op(&mut self, op: Op) {
+        self.lines.push(Line::Op(op, None));
+    }
+
+    fn note(&mut self, op: Op, comment: &str) {
+        self.lines.push(Line::Op(op, Some(comment.to_string())));
+    }
+
+    fn comment(&mut self, text: String) {
+        self.lines.push(Line::Comment(text));
+    }
+
+    fn label(&mut self, name: &str) {
+        self.lines.push(Line::Label(name.to_string()));
+    }
+}
+
+// The kernel services generated programs use.
+#[derive(Clone, Copy)]
+enum Syscall {
+    Read,
+    Write,
+    Exit,
+    Mmap,
+}
+
+fn label(name: &str) -> Mem {
+    Mem::Label(name.to_string())
+}
@@ -1,100 +1,293 @@
-use crate::lex::{Token, TokenType};
+use crate::{
+    config::{BoundsPolicy, Config, OverflowPolicy},
+    lex::{Span, Token, TokenType},
+};
 
+/// The optimized, pointer-relative form of a program.
 pub struct Representation {
     pub instructions: Vec<Instruction>,
+    /// Where in the source each instruction came from: the first token of a
+    /// merged `+`/`-` or `<`/`>` run, or the token itself otherwise.
+    pub spans: Vec<Span>,
+    // Decides which runs may be folded without hiding a trap
+    config: Config,
+}
+
+impl Default for Representation {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Representation {
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    /// An empty representation for a program run with `config`. With the
+    /// trapping bounds or overflow policy, runs are only folded as far as
+    /// that can't skip past a trap.
+    pub fn with_config(config: Config) -> Self {
         Representation {
             instructions: vec![],
+            spans: vec![],
+            config,
         }
     }
 
-   pub fn parse(&mut self, tokens: &Vec<Token>) {
-        let mut count = 0;
-        let mut ptr = 0;
+    /// Lowers the tokens into pointer-relative instructions.
+    ///
+    /// Pointer moves inside a straight run of code are folded into the
+    /// `offset` of the instructions that follow them, and are only
+    /// materialised as a `Move` right before a loop boundary (and at the very
+    /// end). That way the pointer is always at the right cell whenever a loop
+    /// tests it, no matter how far the loop body moved it.
+    ///
+    /// A `+`/`-` run that turns around is split where it turns if cells trap
+    /// on leaving their range, so `-+` still traps on a zero cell. If the
+    /// pointer traps on leaving the tape, moves aren't folded at all: each
+    /// `<` and `>` is checked where it is taken, so the trap blames the one
+    /// that left and the cell it reached, as the token interpreter does.
+    pub fn parse(&mut self, tokens: &[Token]) {
+        let mut count = Pending::default();
+        let mut offset = Pending::default();
 
         for token in tokens {
             match token.token_type() {
                 TokenType::Increment => {
-                    count += 1;
+                    self.turn_count(&mut count, 1, offset.value);
+                    count.add(1, token);
                 }
                 TokenType::Decrement => {
-                    count -= 1;
+                    self.turn_count(&mut count, -1, offset.value);
+                    count.add(-1, token);
                 }
                 TokenType::Print => {
-                    if count != 0 {
-                        self.instructions.push(Instruction::Sum(count, ptr));
-                        count = 0; // reset after flushing
-                    }
-                    self.instructions.push(Instruction::Print(ptr));
+                    self.flush(&mut count, offset.value);
+                    self.push(
+                        Instruction::Print {
+                            offset: offset.value,
+                        },
+                        token.span(),
+                    );
                 }
                 TokenType::Read => {
-                    if count != 0 {
-                        self.instructions.push(Instruction::Sum(count, ptr));
-                        count = 0;
-                    }
-                    self.instructions.push(Instruction::Read(ptr));
+                    self.flush(&mut count, offset.value);
+                    self.push(
+                        Instruction::Read {
+                            offset: offset.value,
+                        },
+                        token.span(),
+                    );
                 }
                 TokenType::LoopStart => {
-                    if count != 0 {
-                        self.instructions.push(Instruction::Sum(count, ptr));
-                        count = 0;
-                    }
-                    self.instructions.push(Instruction::LoopStart(ptr));
+                    self.flush(&mut count, offset.value);
+                    self.settle(&mut offset);
+                    self.push(Instruction::LoopStart, token.span());
                 }
                 TokenType::LoopEnd => {
-                    if count != 0 {
-                        self.instructions.push(Instruction::Sum(count, ptr));
-                        count = 0;
-                    }
-                    self.instructions.push(Instruction::LoopEnd(ptr));
+                    self.flush(&mut count, offset.value);
+                    self.settle(&mut offset);
+                    self.push(Instruction::LoopEnd, token.span());
                 }
                 TokenType::MoveLeft => {
-                    if count != 0 {
-                        self.instructions.push(Instruction::Sum(count, ptr));
-                        count = 0;
+                    self.flush(&mut count, offset.value);
+                    offset.add(-1, token);
+                    if self.config.bounds == BoundsPolicy::Trap {
+                        self.settle(&mut offset);
                     }
-                    ptr = (ptr + 30000 - 1) % 30000;
                 }
                 TokenType::MoveRight => {
-                    if count != 0 {
-                        self.instructions.push(Instruction::Sum(count, ptr));
-                        count = 0;
+                    self.flush(&mut count, offset.value);
+                    offset.add(1, token);
+                    if self.config.bounds == BoundsPolicy::Trap {
+                        self.settle(&mut offset);
                     }
-                    ptr = (ptr + 1) % 30000;
                 }
                 _ => {}
             }
         }
+
+        self.flush(&mut count, offset.value);
+        self.settle(&mut offset);
     }
 
-    fn get(&self, ptr: usize) -> i32 {
-        // Fix: Loop from end to start
-        for i in (0..self.instructions.len()).rev() {
-            if let Instruction::Sum(x, y) = self.instructions[i] {
-                if ptr == y {
-                    return x;
-                }
-            }
-        } 
-        0
+    fn push(&mut self, ins: Instruction, span: Span) {
+        self.instructions.push(ins);
+        self.spans.push(span);
+    }
+
+    // Emits the pending `+`/`-` run for the cell at `offset`.
+    fn flush(&mut self, count: &mut Pending<i32>, offset: isize) {
+        if count.value != 0 {
+            let delta = count.value;
+            self.push(Instruction::Add { offset, delta }, count.span);
+        }
+        *count = Pending::default(); // reset after flushing
+    }
+
+    // Flushes the pending `+`/`-` run before a `step` the other way, if cells
+    // trap on leaving their range. Each part then only goes one way, so
+    // checking where it ends checks everything it went through.
+    fn turn_count(&mut self, count: &mut Pending<i32>, step: i32, offset: isize) {
+        if self.config.overflow == OverflowPolicy::Trap && count.last * step < 0 {
+            self.flush(count, offset);
+        }
+    }
+
+    // Actually moves the pointer by the offset accumulated so far.
+    fn settle(&mut self, offset: &mut Pending<isize>) {
+        if offset.value != 0 {
+            self.push(Instruction::Move(offset.value), offset.span);
+        }
+        *offset = Pending::default();
+    }
+}
+
+// A run of `+`/`-` or `<`/`>` being merged, where it started and which way
+// it last went.
+#[derive(Default)]
+struct Pending<T> {
+    value: T,
+    span: Span,
+    started: bool,
+    last: T,
+}
+
+impl<T: std::ops::AddAssign + Copy> Pending<T> {
+    fn add(&mut self, step: T, token: &Token) {
+        if !self.started {
+            self.span = token.span();
+            self.started = true;
+        }
+        self.value += step;
+        self.last = step;
     }
 }
 
 /// IR Instructions:
-/// - Sum(value, ptr): Add/subtract value at memory position ptr
-/// - Read(ptr): Read input byte into memory position ptr 
-/// - Print(ptr): Output byte at memory position ptr
-/// - LoopStart(ptr): Begin loop, continue if byte at ptr is non-zero
-/// - LoopEnd(ptr): End loop, jump back to matching LoopStart if byte at ptr is non-zero
+/// - Add { offset, delta }: Add/subtract delta at memory position ptr + offset
+/// - Move(n): Move the pointer n cells (negative moves left)
+/// - Read { offset }: Read input byte into memory position ptr + offset
+/// - Print { offset }: Output byte at memory position ptr + offset
+/// - LoopStart: Begin loop, continue if byte at ptr is non-zero
+/// - LoopEnd: End loop, jump back to matching LoopStart if byte at ptr is non-zero
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
-    Sum(i32, usize),
-    Read(usize),
-    Print(usize),
-    LoopStart(usize),
-    LoopEnd(usize),
+    Add { offset: isize, delta: i32 },
+    Move(isize),
+    Read { offset: isize },
+    Print { offset: isize },
+    LoopStart,
+    LoopEnd,
+}
+
+/// What the helper functions a source backend (Rust, JavaScript) writes
+/// for a program look like: which ones are called, which take the code to
+/// blame, and whether they track where cell 0 went.
+///
+/// Helper templates stand `$AT` for the parameter naming the code to blame,
+/// and `$PASS` for passing it on to `seek`; [`Helpers::fill`] fills both in.
+pub(crate) struct Helpers {
+    pub moves: bool,
+    pub adds: bool,
+    pub prints: bool,
+    pub reads: bool,
+    /// Whether the growing `seek` has to remember where cell 0 went, for
+    /// `add` to report it.
+    pub origin: bool,
+    bounds_trap: bool,
+    overflow_trap: bool,
+}
+
+impl Helpers {
+    pub fn new(rep: &Representation, config: Config) -> Self {
+        let has = |f: fn(&Instruction) -> bool| rep.instructions.iter().any(f);
+        let adds = has(|ins| matches!(ins, Instruction::Add { .. }));
+        let overflow_trap = config.overflow == OverflowPolicy::Trap;
+        Self {
+            moves: has(|ins| matches!(ins, Instruction::Move(_))),
+            adds,
+            prints: has(|ins| matches!(ins, Instruction::Print { .. })),
+            reads: has(|ins| matches!(ins, Instruction::Read { .. })),
+            origin: config.bounds == BoundsPolicy::Grow && overflow_trap && adds,
+            bounds_trap: config.bounds == BoundsPolicy::Trap,
+            overflow_trap,
+        }
+    }
+
+    /// Whether anything finds a cell through `seek`.
+    pub fn seeks(&self) -> bool {
+        self.moves || self.adds || self.prints || self.reads
+    }
+
+    /// Whether anything can stop the program with a runtime error.
+    pub fn traps(&self) -> bool {
+        (self.bounds_trap && self.seeks()) || (self.overflow_trap && self.adds)
+    }
+
+    /// Whether the helpers finding a cell take the code to blame, as `seek`
+    /// does when the pointer traps.
+    pub fn seek_at(&self) -> bool {
+        self.bounds_trap
+    }
+
+    /// Whether `add` takes the code to blame.
+    pub fn add_at(&self) -> bool {
+        self.bounds_trap || self.overflow_trap
+    }
+
+    /// The argument naming `span` as the code to blame, if the helper behind
+    /// `ins` takes one.
+    pub fn at_arg(&self, ins: &Instruction, span: Span) -> String {
+        let at = match ins {
+            Instruction::Add { .. } => self.add_at(),
+            _ => self.seek_at(),
+        };
+        if at {
+            format!(", \"{}\"", span)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Fills in `helper`: `$AT` becomes `param` if `at` says it takes the
+    /// code to blame, and `$PASS` passes that on if `seek` takes it too.
+    pub fn fill(&self, helper: &str, at: bool, param: &str) -> String {
+        let pass = if self.seek_at() { ", at" } else { "" };
+        helper
+            .replace("$AT", if at { param } else { "" })
+            .replace("$PASS", pass)
+    }
+}
+
+/// Numbers loops in the order they start, so code generators can give the
+/// labels of each one a unique name.
+#[derive(Debug, Default)]
+pub struct Loops {
+    count: usize,
+    open: Vec<usize>,
+}
+
+impl Loops {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of the loop a `LoopStart` opens.
+    pub fn start(&mut self) -> usize {
+        let id = self.count;
+        self.count += 1;
+        self.open.push(id);
+        id
+    }
+
+    /// The number of the loop a `LoopEnd` closes.
+    pub fn end(&mut self) -> usize {
+        // Should not happen if the parser checked the syntax correctly.
+        self.open
+            .pop()
+            .expect("Unmatched loop end encountered during code generation.")
+    }
 }
@@ -0,0 +1,603 @@
+//! Compiles the IR to x86-64 machine code in memory and runs it in-process.
+//!
+//! The code is lowered to the same [`x86`] instructions the native backend
+//! prints and encodes, but it is a function called from Rust rather than a
+//! program of its own: `,` and `.` call back into Rust for their I/O, and a
+//! trap returns to the caller instead of exiting. A growable tape is a
+//! fixed 1 GiB mapping, and leaving it is an error rather than a crash.
+//! Only built for x86-64 Linux.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    ffi::c_void,
+    io::{self, BufWriter, Read, Write},
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    config::{BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy, Target},
+    error::{CodegenError, Error, RuntimeError},
+    ir::{Instruction, Loops, Representation},
+    lex::Span,
+    x86::{self, Alu, Cond, Line, Mem, Op, Operand, Reg, Size},
+};
+
+// Size of the mapping behind a growable tape, as in compiled programs.
+const GROWABLE_TAPE_BYTES: usize = 0x4000_0000;
+
+// What the code returns once the program is done, and what a callback
+// returns when reading or writing failed, which the code returns in turn.
+// Traps return their index plus one.
+const DONE: i32 = 0;
+const FAILED: i32 = -2;
+
+// Offsets into `State`.
+const IO: i32 = 0;
+const PUT: i32 = 8;
+const GET: i32 = 16;
+const FAULT: i32 = 24;
+
+/// Compiles the IR to machine code, honouring the options in the config.
+pub struct JitCompiler {
+    lines: Vec<Line>,
+    loops: Loops,
+    read_count: usize,
+    traps: Vec<(Trap, Mem)>, // one per check that can stop the program
+    config: Config,
+}
+
+impl Default for JitCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JitCompiler {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            lines: vec![],
+            loops: Loops::new(),
+            read_count: 0,
+            traps: Vec::new(),
+            config,
+        }
+    }
+
+    /// Compiles `rep` into executable memory.
+    pub fn compile(&mut self, rep: &Representation) -> Result<JitProgram, Error> {
+        let lines = self.lower(rep)?;
+        let mut code = x86::encode(&lines, Target::X86_64)?;
+        let memory = Mapping::new(code.bytes.len())?;
+        code.link(memory.ptr as u64, &HashMap::new())?;
+        memory.load(&code.bytes)?;
+
+        Ok(JitProgram {
+            memory,
+            entry: code.labels[x86::ENTRY],
+            traps: self.traps.drain(..).map(|(trap, _)| trap).collect(),
+            config: self.config,
+        })
+    }
+
+    /// Lowers the IR to x86-64 instructions for a function taking the
+    /// `State` and the current cell, followed by the start and end of the
+    /// tape, as the System V ABI passes arguments.
+    pub fn lower(&mut self, rep: &Representation) -> Result<Vec<Line>, CodegenError> {
+        let size = self.size()?;
+
+        self.label(x86::ENTRY);
+        // Five pushes leave the stack aligned for the callbacks.
+        for reg in SAVED {
+            self.op(Op::Push(reg));
+        }
+        self.note(
+            Op::Mov(Size::Qword, Reg::R14.into(), Reg::Rdi.into()),
+            "state",
+        );
+        self.note(
+            Op::Mov(Size::Qword, Reg::Rbp.into(), Reg::Rsi.into()),
+            "current cell pointer",
+        );
+        self.note(
+            Op::Mov(Size::Qword, Reg::R12.into(), Reg::Rdx.into()),
+            "tape start",
+        );
+        self.note(
+            Op::Mov(Size::Qword, Reg::R13.into(), Reg::Rcx.into()),
+            "tape end",
+        );
+
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
+            match ins {
+                Instruction::Add { offset, delta } => {
+                    let addr = self.checked_addr(*offset, *span);
+                    if self.config.overflow == OverflowPolicy::Trap {
+                        self.checked_add(addr, *delta, *span);
+                        continue;
+                    }
+                    let value = match size {
+                        Size::Byte => *delta as u8 as i64,
+                        Size::Word => *delta as u16 as i64,
+                        _ => *delta as u32 as i64,
+                    };
+                    self.op(Op::Alu(Alu::Add, size, addr.into(), value.into()));
+                }
+                Instruction::Move(n) => {
+                    self.op(Op::Lea(Reg::Rbx, self.addr(*n)));
+                    self.check_addr(Mem::Base(Reg::Rbx, 0), *span);
+                    self.op(Op::Mov(Size::Qword, Reg::Rbp.into(), Reg::Rbx.into()));
+                }
+                Instruction::Print { offset } => {
+                    self.comment(format!("Print character at offset {}", offset));
+                    // The low byte comes first, whatever the width of the cell.
+                    let addr = self.checked_addr(*offset, *span);
+                    self.op(Op::Movzx(Reg::Rsi, addr));
+                    self.callback(PUT);
+                }
+                Instruction::Read { offset } => {
+                    self.comment(format!("Read character into offset {}", offset));
+                    let addr = self.checked_addr(*offset, *span);
+                    self.callback(GET);
+                    self.store_input(addr, size);
+                }
+                Instruction::LoopStart => {
+                    let loop_id = self.loops.start();
+                    self.label(&format!("loop_start_{}", loop_id));
+                    self.op(Op::Alu(Alu::Cmp, size, self.addr(0).into(), 0.into()));
+                    self.op(Op::J(Cond::E, format!("loop_end_{}", loop_id)));
+                }
+                Instruction::LoopEnd => {
+                    let loop_id = self.loops.end();
+                    self.op(Op::Alu(Alu::Cmp, size, self.addr(0).into(), 0.into()));
+                    self.op(Op::J(Cond::Ne, format!("loop_start_{}", loop_id)));
+                    self.label(&format!("loop_end_{}", loop_id));
+                }
+            }
+        }
+
+        self.lines.push(Line::Blank);
+        self.note(
+            Op::Mov(Size::Dword, Reg::Rax.into(), (DONE as i64).into()),
+            "done",
+        );
+        self.label("exit");
+        for reg in SAVED.into_iter().rev() {
+            self.op(Op::Pop(reg));
+        }
+        self.op(Op::Ret);
+
+        self.routines();
+        Ok(std::mem::take(&mut self.lines))
+    }
+
+    fn size(&self) -> Result<Size, CodegenError> {
+        match self.config.cell {
+            CellWidth::U8 => Ok(Size::Byte),
+            CellWidth::U16 => Ok(Size::Word),
+            CellWidth::U32 => Ok(Size::Dword),
+            CellWidth::Unbounded => Err(CodegenError::Unsupported(
+                "Unbounded cells are not supported by the JIT".to_string(),
+            )),
+        }
+    }
+
+    // Address of the cell `offset` cells away from the current one, brought
+    // back on the tape as the bounds policy says. The pointer itself never
+    // leaves the tape, so only cells at an offset need checking.
+    fn checked_addr(&mut self, offset: isize, span: Span) -> Mem {
+        if offset == 0 {
+            return self.addr(offset);
+        }
+        self.op(Op::Lea(Reg::Rbx, self.addr(offset)));
+        let addr = Mem::Base(Reg::Rbx, 0);
+        self.check_addr(addr.clone(), span);
+        addr
+    }
+
+    // Wraps or checks the address in rbx; `span` is the code to blame if it
+    // is off the tape, or off the mapping a growable tape lives in.
+    fn check_addr(&mut self, addr: Mem, span: Span) {
+        let trap = match self.config.bounds {
+            BoundsPolicy::Wrap => return self.op(Op::Call("wrap_addr".to_string())),
+            BoundsPolicy::Trap => Trap::OutOfBounds(span),
+            BoundsPolicy::Grow => Trap::TapeFull(span),
+        };
+        let trap = self.trap(trap, addr);
+        self.op(Op::Alu(
+            Alu::Cmp,
+            Size::Qword,
+            Reg::Rbx.into(),
+            Reg::R12.into(),
+        ));
+        self.op(Op::J(Cond::B, trap.clone()));
+        self.op(Op::Alu(
+            Alu::Cmp,
+            Size::Qword,
+            Reg::Rbx.into(),
+            Reg::R13.into(),
+        ));
+        self.op(Op::J(Cond::Ae, trap));
+    }
+
+    // Adds `delta` to the cell at `addr`, stopping the program if it goes
+    // below 0 or past its largest value; `span` is the code to blame.
+    fn checked_add(&mut self, addr: Mem, delta: i32, span: Span) {
+        let trap = self.trap(
+            Trap::Overflow {
+                span,
+                below_zero: delta < 0,
+            },
+            addr.clone(),
+        );
+        let (size, max) = match self.config.cell {
+            CellWidth::U8 => (Size::Byte, u8::MAX as u32),
+            CellWidth::U16 => (Size::Word, u16::MAX as u32),
+            _ => (Size::Dword, u32::MAX),
+        };
+        if delta.unsigned_abs() > max {
+            self.note(Op::Jmp(trap), "always overflows");
+            return;
+        }
+        // The carry flag is set exactly when the result doesn't fit.
+        let alu = if delta < 0 { Alu::Sub } else { Alu::Add };
+        let value = delta.unsigned_abs() as i64;
+        self.op(Op::Alu(alu, size, addr.into(), value.into()));
+        self.op(Op::J(Cond::C, trap));
+    }
+
+    // Registers a trap about the cell at `addr`, returning the label of the
+    // code that reports it.
+    fn trap(&mut self, trap: Trap, addr: Mem) -> String {
+        self.traps.push((trap, addr));
+        format!("trap_{}", self.traps.len() - 1)
+    }
+
+    // Calls the callback at `offset` in the state, with the I/O context as
+    // its first argument, giving up if it failed.
+    fn callback(&mut self, offset: i32) {
+        let state = |offset| Mem::Base(Reg::R14, offset);
+        self.op(Op::Mov(Size::Qword, Reg::Rdi.into(), state(IO).into()));
+        self.op(Op::CallPtr(state(offset)));
+        let failed = Operand::Imm(FAILED as i64);
+        self.op(Op::Alu(Alu::Cmp, Size::Dword, Reg::Rax.into(), failed));
+        self.op(Op::J(Cond::E, "exit".to_string()));
+    }
+
+    // Moves the byte in eax into the cell at `addr`, or applies the EOF
+    // policy if eax is -1.
+    fn store_input(&mut self, addr: Mem, size: Size) {
+        let read_id = self.read_count;
+        self.read_count += 1;
+        self.op(Op::Alu(Alu::Cmp, Size::Dword, Reg::Rax.into(), (-1).into()));
+        self.op(Op::J(Cond::E, format!("read_eof_{}", read_id)));
+        self.op(Op::Mov(size, addr.clone().into(), Reg::Rax.into()));
+        self.op(Op::Jmp(format!("read_done_{}", read_id)));
+        self.label(&format!("read_eof_{}", read_id));
+        match self.config.eof {
+            EofPolicy::Unchanged => {}
+            EofPolicy::Zero => self.op(Op::Mov(size, addr.into(), 0.into())),
+            EofPolicy::Max => self.op(Op::Mov(size, addr.into(), (-1).into())),
+        }
+        self.label(&format!("read_done_{}", read_id));
+    }
+
+    // The routine behind `check_addr` for a wrapping tape, and the code
+    // reporting each trap.
+    fn routines(&mut self) {
+        if self.config.bounds == BoundsPolicy::Wrap {
+            self.lines.push(Line::Blank);
+            self.comment("Bring rbx back on the tape from either side".to_string());
+            self.label("wrap_addr");
+            self.op(Op::Alu(
+                Alu::Cmp,
+                Size::Qword,
+                Reg::Rbx.into(),
+                Reg::R12.into(),
+            ));
+            self.op(Op::J(Cond::B, "wrap_below".to_string()));
+            self.op(Op::Alu(
+                Alu::Cmp,
+                Size::Qword,
+                Reg::Rbx.into(),
+                Reg::R13.into(),
+            ));
+            self.op(Op::J(Cond::B, "wrap_done".to_string()));
+            self.op(Op::Alu(
+                Alu::Sub,
+                Size::Qword,
+                Reg::Rbx.into(),
+                Reg::R13.into(),
+            ));
+            self.op(Op::Alu(
+                Alu::Add,
+                Size::Qword,
+                Reg::Rbx.into(),
+                Reg::R12.into(),
+            ));
+            self.op(Op::Jmp("wrap_addr".to_string()));
+            self.label("wrap_below");
+            self.op(Op::Alu(
+                Alu::Add,
+                Size::Qword,
+                Reg::Rbx.into(),
+                Reg::R13.into(),
+            ));
+            self.op(Op::Alu(
+                Alu::Sub,
+                Size::Qword,
+                Reg::Rbx.into(),
+                Reg::R12.into(),
+            ));
+            self.op(Op::Jmp("wrap_addr".to_string()));
+            self.label("wrap_done");
+            self.op(Op::Ret);
+        }
+
+        // Each leaves the address of the cell in the state for the error.
+        let traps = std::mem::take(&mut self.traps);
+        for (id, (_, addr)) in traps.iter().enumerate() {
+            self.lines.push(Line::Blank);
+            self.label(&format!("trap_{}", id));
+            self.op(Op::Lea(Reg::Rax, addr.clone()));
+            let fault = Mem::Base(Reg::R14, FAULT);
+            self.op(Op::Mov(Size::Qword, fault.into(), Reg::Rax.into()));
+            let code = id as i64 + 1;
+            self.op(Op::Mov(Size::Dword, Reg::Rax.into(), code.into()));
+            self.op(Op::Jmp("exit".to_string()));
+        }
+        self.traps = traps;
+    }
+
+    // Memory operand for the cell `offset` cells away from the current one.
+    fn addr(&self, offset: isize) -> Mem {
+        let bytes = self.config.cell.bytes().unwrap_or(1) as isize;
+        Mem::Base(Reg::Rbp, (offset * bytes) as i32)
+    }
+
+    fn op(&mut self, op: Op) {
+        self.lines.push(Line::Op(op, None));
+    }
+
+    fn note(&mut self, op: Op, comment: &str) {
+        self.lines.push(Line::Op(op, Some(comment.to_string())));
+    }
+
+    fn comment(&mut self, text: String) {
+        self.lines.push(Line::Comment(text));
+    }
+
+    fn label(&mut self, name: &str) {
+        self.lines.push(Line::Label(name.to_string()));
+    }
+}
+
+// The callee-saved registers the code uses: the current cell, the address
+// being checked, the ends of the tape and the state.
+const SAVED: [Reg; 5] = [Reg::Rbp, Reg::Rbx, Reg::R12, Reg::R13, Reg::R14];
+
+// What a check that stopped the program was about.
+#[derive(Debug, Clone, Copy)]
+enum Trap {
+    OutOfBounds(Span),
+    Overflow { span: Span, below_zero: bool },
+    TapeFull(Span),
+}
+
+/// A program compiled by [`JitCompiler`], which can be run any number of
+/// times, each on a fresh tape.
+pub struct JitProgram {
+    memory: Mapping,
+    entry: usize,
+    traps: Vec<Trap>,
+    config: Config,
+}
+
+impl JitProgram {
+    /// Runs the program with the given streams.
+    ///
+    /// Output is buffered and flushed whenever the program waits for input
+    /// and when the run finishes, as with [`Runner`](crate::Runner).
+    pub fn run_with<R: Read, W: Write>(&self, mut input: R, mut output: W) -> Result<(), Error> {
+        let bytes = self.config.cell.bytes().unwrap_or(1);
+        let len = self.config.tape_len.max(1) * bytes;
+        let mut fixed = Vec::new();
+        let mut growable = None;
+        let (start, origin, end) = match self.config.bounds {
+            // Untouched pages of the mapping are never backed by memory.
+            BoundsPolicy::Grow => {
+                let tape = growable.insert(Mapping::new(GROWABLE_TAPE_BYTES)?);
+                let origin = tape.ptr.wrapping_add(GROWABLE_TAPE_BYTES / 2);
+                (tape.ptr, origin, tape.ptr.wrapping_add(GROWABLE_TAPE_BYTES))
+            }
+            _ => {
+                fixed.resize(len, 0u8);
+                let start = fixed.as_mut_ptr();
+                (start, start, start.wrapping_add(len))
+            }
+        };
+
+        let mut io = Io {
+            input: &mut input,
+            output: BufWriter::new(&mut output),
+            failure: None,
+        };
+        let mut state = State {
+            io: &mut io as *mut Io as *mut c_void,
+            put,
+            get,
+            fault: 0,
+        };
+        // SAFETY: the memory holds the code `lower` generated for this
+        // signature, and it checks every address it touches against `start`
+        // and `end`, the fixed tape or the mapping of a growable one.
+        let result = unsafe {
+            let code: extern "C" fn(*mut State, *mut u8, *mut u8, *mut u8) -> i32 =
+                std::mem::transmute(self.memory.ptr.add(self.entry));
+            code(&mut state, origin, start, end)
+        };
+        drop(growable);
+
+        match io.failure.take() {
+            Some(Failure::Io(e)) => return Err(e.into()),
+            Some(Failure::Panic(payload)) => panic::resume_unwind(payload),
+            None => {}
+        }
+        io.output.flush()?;
+        let trap = match result {
+            DONE => return Ok(()),
+            code => self.traps[code as usize - 1],
+        };
+        let cell = (state.fault as isize - origin as isize) / bytes as isize;
+        Err(match trap {
+            Trap::OutOfBounds(span) => RuntimeError::OutOfBounds {
+                span,
+                cell,
+                tape_len: self.config.tape_len.max(1),
+            },
+            Trap::Overflow { span, below_zero } => RuntimeError::Overflow {
+                span,
+                cell,
+                below_zero,
+            },
+            Trap::TapeFull(span) => RuntimeError::TapeFull { span, cell },
+        }
+        .into())
+    }
+}
+
+// What the code gets from `run_with`. Its layout is what the offsets at the
+// top say.
+#[repr(C)]
+struct State {
+    io: *mut c_void,
+    put: extern "C" fn(*mut c_void, u32) -> i32,
+    get: extern "C" fn(*mut c_void) -> i32,
+    /// The address of the cell a trap was about
+    fault: usize,
+}
+
+// The streams of a run, and what went wrong with them if anything did.
+struct Io<'a> {
+    input: &'a mut dyn Read,
+    output: BufWriter<&'a mut dyn Write>,
+    failure: Option<Failure>,
+}
+
+enum Failure {
+    Io(io::Error),
+    Panic(Box<dyn Any + Send>),
+}
+
+// Writes `byte`, returning 0 or `FAILED`.
+extern "C" fn put(io: *mut c_void, byte: u32) -> i32 {
+    guarded(io, |io| io.output.write_all(&[byte as u8]).map(|()| 0))
+}
+
+// Reads a byte, flushing first so prompts show up. Returns -1 at the end of
+// input, or `FAILED`.
+extern "C" fn get(io: *mut c_void) -> i32 {
+    guarded(io, |io| {
+        io.output.flush()?;
+        let mut buffer = [0; 1];
+        loop {
+            match io.input.read(&mut buffer) {
+                Ok(0) => return Ok(-1),
+                Ok(_) => return Ok(buffer[0] as i32),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    })
+}
+
+// Runs a callback on the `Io` behind `io`, keeping errors and panics for
+// `run_with` rather than letting them into the generated code.
+fn guarded(io: *mut c_void, f: impl FnOnce(&mut Io) -> io::Result<i32>) -> i32 {
+    // SAFETY: `io` is the `Io` `run_with` put in the state, which outlives
+    // the call into the code.
+    let io = unsafe { &mut *(io as *mut Io) };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(io))) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            io.failure = Some(Failure::Io(e));
+            FAILED
+        }
+        Err(payload) => {
+            io.failure = Some(Failure::Panic(payload));
+            FAILED
+        }
+    }
+}
+
+// Anonymous memory from the kernel, unmapped when dropped.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_NORESERVE: i32 = 0x4000;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+impl Mapping {
+    // `len` zeroed, writable bytes, at least one.
+    fn new(len: usize) -> io::Result<Self> {
+        let len = len.max(1);
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE;
+        // SAFETY: a fresh anonymous mapping aliases nothing.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                flags,
+                -1,
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    // Copies `code` in and makes the mapping executable instead of writable.
+    fn load(&self, code: &[u8]) -> io::Result<()> {
+        // SAFETY: the mapping is at least as long as `code` and nothing else
+        // points into it yet.
+        unsafe {
+            std::ptr::copy_nonoverlapping(code.as_ptr(), self.ptr, code.len());
+            if mprotect(self.ptr as *mut c_void, self.len, PROT_READ | PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping came from `mmap` and nothing uses it any more.
+        unsafe {
+            munmap(self.ptr as *mut c_void, self.len);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Translates the IR to a self-contained JavaScript module.
+//!
+//! The module exports a `run` function that takes the input as a function
+//! returning one byte at a time (or a string, or an array of bytes) and
+//! hands each byte printed to an `output` callback, so programs can be
+//! embedded in web pages and Node scripts alike. Started as `node prog.mjs`
+//! it runs on stdin and stdout instead.
+
+use crate::{
+    config::{BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy},
+    error::CodegenError,
+    ir::{Helpers, Instruction, Representation},
+};
+
+/// Generates an ES module from the IR.
+pub struct JsGenerator {
+    config: Config,
+}
+
+impl Default for JsGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsGenerator {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self { config }
+    }
+
+    pub fn generate(&self, rep: &Representation) -> Result<String, CodegenError> {
+        let (array, max) = match self.config.cell {
+            CellWidth::U8 => ("Uint8Array", "0xff"),
+            CellWidth::U16 => ("Uint16Array", "0xffff"),
+            CellWidth::U32 => ("Uint32Array", "0xffffffff"),
+            CellWidth::Unbounded => {
+                return Err(CodegenError::Unsupported(
+                    "Unbounded cells are not supported by the JavaScript backend".to_string(),
+                ))
+            }
+        };
+        let used = Helpers::new(rep, self.config);
+
+        let mut out = Vec::new();
+        out.push(format!("const TAPE_LEN = {};", self.config.tape_len.max(1)));
+        out.push(format!("const CELL_MAX = {};", max));
+        out.push("".to_string());
+        out.push(
+            "/** What `run` throws when a cell or the pointer goes where the options
+ * don't allow. */
+export class RuntimeError extends Error {}"
+                .to_string(),
+        );
+        out.push("".to_string());
+        out.push(
+            "/**
+ * Runs the program. `input` returns the next byte each time it is called,
+ * or null at the end of input; a string or an array of bytes works too.
+ * `output` is called with each byte printed. Without it, the bytes printed
+ * are returned instead.
+ */
+export function run({ input = null, output = null } = {}) {"
+                .to_string(),
+        );
+        out.push(format!("    let tape = new {}(TAPE_LEN);", array));
+        out.push("    let p = 0;".to_string());
+        if used.origin {
+            out.push("    let origin = 0; // where cell 0 is now".to_string());
+        }
+        out.push("    const printed = [];".to_string());
+        if used.prints {
+            out.push("    const put = output ?? ((byte) => printed.push(byte));".to_string());
+        }
+        if used.reads {
+            out.push("    const get = reader(input);".to_string());
+        }
+        for helper in self.helpers(&used, array) {
+            out.push("".to_string());
+            out.push(helper);
+        }
+        out.push("".to_string());
+
+        let mut depth = 1;
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
+            if *ins == Instruction::LoopEnd {
+                depth -= 1;
+            }
+            let at = used.at_arg(ins, *span);
+            let line = match ins {
+                Instruction::Add { offset, delta } => format!("add({}, {}{});", offset, delta, at),
+                Instruction::Move(n) => format!("p = seek({}{});", n, at),
+                Instruction::Print { offset } => format!("print({}{});", offset, at),
+                Instruction::Read { offset } => format!("read({}{});", offset, at),
+                Instruction::LoopStart => "while (tape[p]) {".to_string(),
+                Instruction::LoopEnd => "}".to_string(),
+            };
+            out.push(format!("{}{}", "    ".repeat(depth), line));
+            if *ins == Instruction::LoopStart {
+                depth += 1;
+            }
+        }
+
+        out.push("    return output ? undefined : Uint8Array.from(printed);".to_string());
+        out.push("}".to_string());
+        if used.reads {
+            out.push("".to_string());
+            out.push(
+                "// Turns the `input` given to `run` into a function returning the next
+// byte, or null at the end of input.
+function reader(input) {
+    if (typeof input === \"function\") return input;
+    const bytes = typeof input === \"string\" ? new TextEncoder().encode(input) : input ?? [];
+    let next = 0;
+    return () => (next < bytes.length ? bytes[next++] : null);
+}"
+                .to_string(),
+            );
+        }
+        out.push("".to_string());
+        out.push(MAIN.to_string());
+        Ok(out.join("\n"))
+    }
+
+    // The functions inside `run` that are called, for the options in the
+    // config. `$AT` stands for the parameter naming the code to blame, and
+    // `$PASS` for passing it on to `seek`.
+    fn helpers(&self, used: &Helpers, array: &str) -> Vec<String> {
+        let mut helpers = Vec::new();
+
+        if used.seeks() {
+            helpers.push(match self.config.bounds {
+                BoundsPolicy::Wrap => {
+                    "    // Index of the cell `offset` away from the current one, coming back in
+    // on the other end of the tape.
+    function seek(offset) {
+        return (((p + offset) % TAPE_LEN) + TAPE_LEN) % TAPE_LEN;
+    }"
+                    .to_string()
+                }
+                BoundsPolicy::Trap => {
+                    "    // Index of the cell `offset` away from the current one, throwing if it
+    // is off the tape.
+    function seek(offset, at) {
+        const i = p + offset;
+        if (i < 0 || i >= TAPE_LEN) {
+            throw new RuntimeError(
+                `Pointer out of bounds at ${at}: cell ${i} is not on the tape of ${TAPE_LEN} cells`,
+            );
+        }
+        return i;
+    }"
+                    .to_string()
+                }
+                BoundsPolicy::Grow => {
+                    let origin = if used.origin {
+                        "\n            origin += before;"
+                    } else {
+                        ""
+                    };
+                    format!(
+                        "    // Index of the cell `offset` away from the current one, growing the
+    // tape to reach it, at least doubling it each time.
+    function seek(offset) {{
+        const i = p + offset;
+        if (i < 0) {{
+            const before = Math.max(-i, tape.length);
+            const bigger = new {array}(tape.length + before);
+            bigger.set(tape, before);
+            tape = bigger;
+            p += before;{origin}
+            return i + before;
+        }}
+        if (i >= tape.length) {{
+            const bigger = new {array}(tape.length + Math.max(i + 1 - tape.length, tape.length));
+            bigger.set(tape);
+            tape = bigger;
+        }}
+        return i;
+    }}",
+                        array = array,
+                        origin = origin
+                    )
+                }
+            });
+        }
+
+        // Each finds the cell before touching `tape`, as growing it makes a
+        // new one.
+        if used.adds {
+            let add = if self.config.overflow == OverflowPolicy::Trap {
+                let origin = if used.origin { " - origin" } else { "" };
+                format!(
+                    "    // Adds `delta` to the cell, throwing if it goes below 0 or past
+    // CELL_MAX.
+    function add(offset, delta$AT) {{
+        const i = seek(offset$PASS);
+        const value = tape[i] + delta;
+        if (value < 0 || value > CELL_MAX) {{
+            const went = value < 0 ? \"below 0\" : \"past its largest value\";
+            throw new RuntimeError(`Cell overflow at ${{at}}: cell ${{i{}}} went ${{went}}`);
+        }}
+        tape[i] = value;
+    }}",
+                    origin
+                )
+            } else {
+                "    function add(offset, delta$AT) {
+        const i = seek(offset$PASS);
+        tape[i] += delta;
+    }"
+                .to_string()
+            };
+            helpers.push(used.fill(&add, used.add_at(), ", at"));
+        }
+
+        if used.prints {
+            let byte = if self.config.cell == CellWidth::U8 {
+                "tape[i]"
+            } else {
+                "tape[i] & 0xff"
+            };
+            helpers.push(format!(
+                "    function print(offset$AT) {{
+        const i = seek(offset$PASS);
+        put({});
+    }}",
+                byte
+            ));
+        }
+
+        if used.reads {
+            let eof = match self.config.eof {
+                EofPolicy::Unchanged => "",
+                EofPolicy::Zero => "\n        else tape[i] = 0;",
+                EofPolicy::Max => "\n        else tape[i] = CELL_MAX;",
+            };
+            helpers.push(format!(
+                "    function read(offset$AT) {{
+        const i = seek(offset$PASS);
+        const byte = get();
+        if (byte !== null && byte !== undefined) tape[i] = byte;{}
+    }}",
+                eof
+            ));
+        }
+
+        helpers
+            .iter()
+            .map(|helper| used.fill(helper, used.seek_at(), ", at"))
+            .collect()
+    }
+}
+
+// Runs the module on stdin and stdout when Node starts it directly, writing
+// each byte as it is printed so output shows up while the program runs.
+const MAIN: &str = "// Runs on stdin and stdout when started as `node prog.mjs`.
+if (typeof process === \"object\" && process.argv?.[1]) {
+    const { pathToFileURL } = await import(\"node:url\");
+    if (import.meta.url === pathToFileURL(process.argv[1]).href) {
+        const { readSync, writeSync } = await import(\"node:fs\");
+        const byte = new Uint8Array(1);
+        try {
+            run({
+                input: () => (readSync(0, byte) === 1 ? byte[0] : null),
+                output: (b) => writeSync(1, Uint8Array.of(b)),
+            });
+        } catch (e) {
+            if (!(e instanceof RuntimeError)) throw e;
+            process.stderr.write(`Runtime error: ${e.message}\\n`);
+            process.exitCode = 1;
+        }
+    }
+}
+";
@@ -1,147 +1,382 @@
+//! Lexing, along with the comment syntax of [`LexMode::Comments`].
+//!
+//! In that mode `// ...` runs to the end of the line and `/* ... */` may span
+//! lines and nest. A comment whose text starts with `@`, such as
+//! `//@ print the result` or `/*@ clear the cell */`, is an *annotation*: it
+//! labels the code after it, up to the next annotation or the end of the
+//! source. Tools can read them back through [`Lexer::annotations`].
+//!
+//! Comments are dropped by default. [`Lexer::keep_comments`] turns them into
+//! [`TokenType::Comment`] tokens, which the rest of the crate skips over, so
+//! the source can be rebuilt from the tokens' spans.
+//!
+//! The lexer looks at each byte once, so it takes linear time, and can be fed
+//! straight from a [`BufRead`] without holding the whole source in memory.
+
+use std::{
+    io::{self, BufRead},
+    mem,
+    ops::Range,
+};
+
+use crate::error::{Error, LexError};
+
+/// What the lexer makes of characters that aren't commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LexMode {
+    /// Ignore them, as brainfuck traditionally does
+    Standard,
+    /// Allow whitespace and comments only
+    #[default]
+    Comments,
+    /// Allow whitespace only
+    Strict,
+}
+
+/// Turns source text into [`Token`]s.
 pub struct Lexer {
     input: String,
-    current: usize,
-    tokens: Vec<Token>,
+    state: State,
+    pos: usize,
     line: usize,
     col: usize,
+    tokens: Vec<Token>,
+    errors: Vec<LexError>,
+    mode: LexMode,
+    keep_comments: bool,
+    annotations: Vec<Annotation>,
+}
+
+// What the lexer is in the middle of between two bytes, and where that
+// started.
+enum State {
+    Code,
+    // A `/`, which had better start a comment
+    Slash {
+        start: Span,
+    },
+    LineComment {
+        start: Span,
+        text: Vec<u8>,
+    },
+    // `last` is the previous byte unless it already closed a `/*` or `*/`
+    BlockComment {
+        start: Span,
+        text: Vec<u8>,
+        depth: usize,
+        last: Option<u8>,
+    },
+    // A character that isn't allowed, `need` bytes long in UTF-8
+    Unexpected {
+        start: Span,
+        bytes: Vec<u8>,
+        need: usize,
+    },
+}
+
+/// A `//@` or `/*@` comment and the code it labels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// What follows the `@`, trimmed
+    pub text: String,
+    /// Where the comment is
+    pub span: Span,
+    /// The tokens it labels, as indices into [`Lexer::tokens`]
+    pub tokens: Range<usize>,
+}
+
+impl Default for Lexer {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
         Self {
             input,
-            current: 0,
-            tokens: Vec::new(),
+            state: State::Code,
+            pos: 0,
             line: 1,
             col: 1,
+            tokens: Vec::new(),
+            errors: Vec::new(),
+            mode: LexMode::default(),
+            keep_comments: false,
+            annotations: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<(), String> {
-        while self.current < self.input.len() {
-            let c = self.input.chars().nth(self.current).unwrap();
-            match c {
-                '+' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::Increment,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
-                }
-                '-' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::Decrement,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
-                }
-                '>' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::MoveRight,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
-                }
-                '<' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::MoveLeft,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
-                }
-                '[' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::LoopStart,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
-                }
-                ']' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::LoopEnd,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
+    pub fn with_mode(mut self, mode: LexMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Emits a [`TokenType::Comment`] for every comment instead of dropping
+    /// it.
+    pub fn keep_comments(mut self, keep: bool) -> Self {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// Lexes the whole input. Characters that aren't allowed are skipped
+    /// over, so every one of them is reported at once.
+    pub fn parse(&mut self) -> Result<(), Vec<LexError>> {
+        let input = mem::take(&mut self.input);
+        for &byte in input.as_bytes() {
+            self.feed(byte);
+        }
+        self.input = input;
+        self.finish()
+    }
+
+    /// Same as [`parse`](Self::parse), reading the source from `reader` as it
+    /// goes instead of taking the string given to [`new`](Self::new).
+    ///
+    /// ```
+    /// let source = std::io::BufReader::new(&b"++[>+<-]"[..]);
+    /// let mut lexer = brainfuck::Lexer::default();
+    /// lexer.parse_from(source).unwrap();
+    /// assert_eq!(lexer.tokens().len(), 9);
+    /// ```
+    pub fn parse_from<R: BufRead>(&mut self, mut reader: R) -> Result<(), Error> {
+        loop {
+            let buf = match reader.fill_buf() {
+                Ok([]) => break,
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for &byte in buf {
+                self.feed(byte);
+            }
+            let len = buf.len();
+            reader.consume(len);
+        }
+        self.finish().map_err(Error::many)
+    }
+
+    fn feed(&mut self, byte: u8) {
+        self.step(byte);
+
+        self.pos += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.col = 1;
+        } else if !is_continuation(byte) {
+            self.col += 1;
+        }
+    }
+
+    // Moves the state machine on by one byte. When a byte ends what came
+    // before it without being part of it, it is looked at again as code.
+    fn step(&mut self, byte: u8) {
+        match mem::replace(&mut self.state, State::Code) {
+            State::Code => self.code(byte),
+            State::Slash { start } => match byte {
+                b'/' => {
+                    let text = vec![];
+                    self.state = State::LineComment { start, text };
                 }
-                '.' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::Print,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
+                b'*' => {
+                    self.state = State::BlockComment {
+                        start,
+                        text: vec![],
+                        depth: 1,
+                        last: None,
+                    };
                 }
-                ',' => {
-                    self.tokens.push(Token {
-                        token_type: TokenType::Read,
-                        pos: self.current,
-                        line: self.line,
-                        col: self.col,
-                    });
-                    self.advance();
+                _ => {
+                    self.unexpected(start, b"/");
+                    self.code(byte);
                 }
-                '/' => {
-                    let ch = self.input.chars().nth(self.current + 1).unwrap();
-
-                    if ch == '/' {
-                        self.comment();
+            },
+            State::LineComment { start, text } if byte == b'\n' => {
+                self.comment(start, text, self.pos);
+                self.code(byte);
+            }
+            State::LineComment { start, mut text } => {
+                text.push(byte);
+                self.state = State::LineComment { start, text };
+            }
+            State::BlockComment {
+                start,
+                mut text,
+                mut depth,
+                last,
+            } => {
+                text.push(byte);
+                let last = match (last, byte) {
+                    (Some(b'/'), b'*') => {
+                        depth += 1;
+                        None
                     }
+                    (Some(b'*'), b'/') => {
+                        depth -= 1;
+                        None
+                    }
+                    _ => Some(byte),
+                };
+
+                if depth == 0 {
+                    text.truncate(text.len() - 2); // the closing `*/`
+                    self.comment(start, text, self.pos + 1);
+                } else {
+                    self.state = State::BlockComment {
+                        start,
+                        text,
+                        depth,
+                        last,
+                    };
                 }
-                '\n' => {
-                    self.line += 1;
-                    self.col = 1;
-                    self.advance();
-                }
-                '\t' | ' ' | '\r' => {
-                    self.advance();
+            }
+            State::Unexpected {
+                start,
+                mut bytes,
+                need,
+            } if is_continuation(byte) => {
+                bytes.push(byte);
+                if bytes.len() == need {
+                    self.unexpected(start, &bytes);
+                } else {
+                    self.state = State::Unexpected { start, bytes, need };
                 }
-                _ => {
-                    return Err(format!(
-                        "Unexpected character `{}` at col {} on line {}",
-                        c, self.col, self.line
-                    ));
+            }
+            State::Unexpected { start, bytes, .. } => {
+                self.unexpected(start, &bytes);
+                self.code(byte);
+            }
+        }
+    }
+
+    fn code(&mut self, byte: u8) {
+        let token_type = match byte {
+            b'+' => TokenType::Increment,
+            b'-' => TokenType::Decrement,
+            b'>' => TokenType::MoveRight,
+            b'<' => TokenType::MoveLeft,
+            b'[' => TokenType::LoopStart,
+            b']' => TokenType::LoopEnd,
+            b'.' => TokenType::Print,
+            b',' => TokenType::Read,
+            b'\n' | b'\t' | b' ' | b'\r' => return,
+            b'/' if self.mode == LexMode::Comments => {
+                self.state = State::Slash { start: self.span() };
+                return;
+            }
+            _ if self.mode == LexMode::Standard => return,
+            _ => {
+                let need = match byte {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                if need == 1 {
+                    self.unexpected(self.span(), &[byte]);
+                } else {
+                    self.state = State::Unexpected {
+                        start: self.span(),
+                        bytes: vec![byte],
+                        need,
+                    };
                 }
+                return;
+            }
+        };
+
+        self.tokens.push(Token {
+            token_type,
+            pos: self.pos,
+            line: self.line,
+            col: self.col,
+            len: 1,
+        });
+    }
+
+    // Wraps up whatever the input ended in the middle of, and reports.
+    fn finish(&mut self) -> Result<(), Vec<LexError>> {
+        match mem::replace(&mut self.state, State::Code) {
+            State::Code => {}
+            State::Slash { start } => self.unexpected(start, b"/"),
+            State::LineComment { start, text } => self.comment(start, text, self.pos),
+            State::BlockComment { start, .. } => {
+                self.errors.push(LexError::UnterminatedComment {
+                    span: Span { len: 2, ..start },
+                });
             }
+            State::Unexpected { start, bytes, .. } => self.unexpected(start, &bytes),
         }
 
+        if let Some(last) = self.annotations.last_mut() {
+            last.tokens.end = self.tokens.len();
+        }
         self.tokens.push(Token {
             token_type: TokenType::Eof,
-            pos: self.current,
+            pos: self.pos,
             line: self.line,
             col: self.col,
+            len: 0,
         });
 
-        Ok(())
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    // Where the current byte is.
+    fn span(&self) -> Span {
+        Span {
+            pos: self.pos,
+            line: self.line,
+            col: self.col,
+            len: 1,
+        }
     }
 
-    fn advance(&mut self) {
-        self.current += 1;
-        self.col += 1;
+    // Reports the character in `bytes`, which may be cut short.
+    fn unexpected(&mut self, start: Span, bytes: &[u8]) {
+        let ch = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.errors.push(LexError::UnexpectedChar {
+            ch,
+            span: Span {
+                len: bytes.len(),
+                ..start
+            },
+        });
     }
 
-    fn comment(&mut self) {
-        self.advance();
-        while self.current < self.input.len() {
-            let c = self.input.chars().nth(self.current).unwrap();
-            if c == '\n' {
-                self.line += 1;
-                self.col = 1;
-                break;
+    // Records a comment that runs from `start` up to the byte at `end`.
+    fn comment(&mut self, start: Span, text: Vec<u8>, end: usize) {
+        let span = Span {
+            len: end - start.pos,
+            ..start
+        };
+        if self.keep_comments {
+            self.tokens.push(Token {
+                token_type: TokenType::Comment,
+                pos: span.pos,
+                line: span.line,
+                col: span.col,
+                len: span.len,
+            });
+        }
+
+        let text = String::from_utf8_lossy(&text);
+        if let Some(text) = text.strip_prefix('@') {
+            if let Some(last) = self.annotations.last_mut() {
+                last.tokens.end = self.tokens.len();
             }
-            self.advance();
+            self.annotations.push(Annotation {
+                text: text.trim().to_string(),
+                span,
+                tokens: self.tokens.len()..self.tokens.len(),
+            });
         }
     }
 
@@ -149,14 +384,15 @@ impl Lexer {
         &self.tokens
     }
 
-    // pub fn eof(&mut self) {
-    //     self.tokens.push(Token {
-    //         token_type: TokenType::Eof,
-    //         pos: self.current,
-    //         line: self.line,
-    //         col: self.col,
-    //     });
-    // }
+    /// The annotations found by `parse`, in source order.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+}
+
+// Whether `byte` carries on a UTF-8 character rather than starting one.
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -165,12 +401,57 @@ pub struct Token {
     pos: usize,
     line: usize,
     col: usize,
+    len: usize,
 }
 
 impl Token {
     pub fn token_type(&self) -> TokenType {
         self.token_type
     }
+
+    /// Where the token is in the source
+    pub fn span(&self) -> Span {
+        Span {
+            pos: self.pos,
+            line: self.line,
+            col: self.col,
+            len: self.len,
+        }
+    }
+
+    /// Byte offset of the token in the source
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// 1-based line of the token
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based column of the token, in characters
+    pub fn col(&self) -> usize {
+        self.col
+    }
+}
+
+/// A position in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Byte offset in the source
+    pub pos: usize,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column, in characters
+    pub col: usize,
+    /// Length in bytes
+    pub len: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.col)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -191,6 +472,8 @@ pub enum TokenType {
     Print,
     /// ,
     Read,
+    /// A comment, only if the lexer was asked to keep them
+    Comment,
     /// End of file
     Eof,
 }
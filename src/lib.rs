@@ -0,0 +1,290 @@
+//! Brainfuck lexer, interpreters and compilers to x86, WebAssembly, C, Rust
+//! and JavaScript.
+//!
+//! The quickest way in is [`Program`]:
+//!
+//! ```no_run
+//! let program = brainfuck::Program::parse("++++++++[>++++++++<-]>+.").unwrap();
+//!
+//! // Run it on stdin/stdout with the bytecode interpreter...
+//! program.run().unwrap();
+//!
+//! // ...or compile it to machine code in memory and run that, on x86-64
+//! // Linux.
+//! program.run_jit().unwrap();
+//!
+//! // ...or turn it into a Linux executable, or FASM source for one.
+//! let exe = program.executable().unwrap();
+//! let asm = program.assembly().unwrap();
+//!
+//! // ...or into a WebAssembly module for WASI.
+//! let wasm = program.wasm().unwrap();
+//!
+//! // ...or into C or Rust for any other platform, or JavaScript for the web.
+//! let c = program.c_source().unwrap();
+//! let rust = program.rust_source().unwrap();
+//! let js = program.js_source().unwrap();
+//! ```
+//!
+//! The individual stages ([`Lexer`], [`SyntaxParser`], [`Representation`],
+//! [`Bytecode`], [`Runner`], [`FasmGenerator`], [`WasmGenerator`], [`CGenerator`],
+//! [`RustGenerator`] and [`JsGenerator`]) are exported as well for when more
+//! control is needed.
+
+pub mod asm;
+pub mod bytecode;
+pub mod cgen;
+pub mod config;
+pub mod diag;
+pub mod elf;
+pub mod error;
+pub mod exe;
+pub mod fasm;
+pub mod ir;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
+pub mod jsgen;
+pub mod lex;
+pub mod parse;
+pub mod rustgen;
+mod tape;
+pub mod wasm;
+pub mod x86;
+
+pub use asm::Dialect;
+pub use bytecode::Bytecode;
+pub use cgen::CGenerator;
+pub use config::{
+    BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy, Target, DEFAULT_TAPE_LEN,
+};
+pub use diag::Diagnostic;
+pub use error::{CodegenError, Error, LexError, RuntimeError, SyntaxError};
+pub use exe::Runner;
+pub use fasm::FasmGenerator;
+pub use ir::{Instruction, Loops, Representation};
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub use jit::{JitCompiler, JitProgram};
+pub use jsgen::JsGenerator;
+pub use lex::{Annotation, LexMode, Lexer, Span, Token, TokenType};
+pub use parse::SyntaxParser;
+pub use rustgen::RustGenerator;
+pub use wasm::WasmGenerator;
+
+use std::io::{self, BufRead, Read, Write};
+
+/// A lexed and syntax-checked brainfuck program, along with the [`Config`]
+/// it is run and compiled with.
+#[derive(Debug, Clone)]
+pub struct Program {
+    tokens: Vec<Token>,
+    jumps: Vec<usize>,
+    config: Config,
+}
+
+impl Program {
+    /// Lexes `source` and checks that its brackets are balanced.
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        Self::parse_with(source, LexMode::default())
+    }
+
+    /// Same as [`parse`](Self::parse), treating characters that aren't
+    /// commands as `mode` says.
+    ///
+    /// ```
+    /// use brainfuck::{LexMode, Program};
+    ///
+    /// let src = "Add two: ++ then print it.";
+    /// assert!(Program::parse(src).is_err());
+    /// assert!(Program::parse_with(src, LexMode::Standard).is_ok());
+    /// ```
+    pub fn parse_with(source: &str, mode: LexMode) -> Result<Self, Error> {
+        let mut lexer = Lexer::new(source.to_string()).with_mode(mode);
+        let errors = lexer.parse().err().into_iter().flatten();
+        Self::check(lexer, errors.map(Error::from).collect())
+    }
+
+    /// Same as [`parse_with`](Self::parse_with), reading the source from
+    /// `reader` as it is lexed.
+    pub fn read_with<R: BufRead>(reader: R, mode: LexMode) -> Result<Self, Error> {
+        let mut lexer = Lexer::default().with_mode(mode);
+        let errors = match lexer.parse_from(reader) {
+            Ok(()) => vec![],
+            Err(Error::Multiple(errors)) => errors,
+            Err(e @ Error::Lex(_)) => vec![e],
+            Err(e) => return Err(e),
+        };
+        Self::check(lexer, errors)
+    }
+
+    // Checks the brackets of what `lexer` found, reporting any problems along
+    // with the `errors` it ran into. Bad characters are skipped, so the
+    // brackets can still be checked.
+    fn check(lexer: Lexer, mut errors: Vec<Error>) -> Result<Self, Error> {
+        let mut syntax = SyntaxParser::new();
+        if let Err(found) = syntax.parse(lexer.tokens()) {
+            errors.extend(found.into_iter().map(Error::from));
+        }
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| e.span().map(|span| span.pos));
+            return Err(Error::many(errors));
+        }
+
+        Ok(Self {
+            tokens: lexer.tokens().clone(),
+            jumps: syntax.jumps().clone(),
+            config: Config::default(),
+        })
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// For every `[` and `]` in `tokens`, the index of its matching bracket.
+    pub fn jumps(&self) -> &[usize] {
+        &self.jumps
+    }
+
+    /// Lowers the program to the optimized IR.
+    pub fn optimize(&self) -> Representation {
+        let mut rep = Representation::with_config(self.config);
+        rep.parse(&self.tokens);
+        rep
+    }
+
+    /// Encodes the optimized IR as bytecode.
+    pub fn bytecode(&self) -> Bytecode {
+        Bytecode::new(&self.optimize())
+    }
+
+    /// Runs the program's bytecode on a fresh tape, reading from stdin and
+    /// writing to stdout.
+    pub fn run(&self) -> Result<(), Error> {
+        self.run_with(io::stdin(), io::stdout())
+    }
+
+    /// Runs the program's bytecode on a fresh tape with the given streams.
+    ///
+    /// ```
+    /// let program = brainfuck::Program::parse(",+.").unwrap();
+    /// let mut out = Vec::new();
+    /// program.run_with(&b"a"[..], &mut out).unwrap();
+    /// assert_eq!(out, b"b");
+    /// ```
+    pub fn run_with<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), Error> {
+        let mut runner = Runner::with_io(vec![], vec![], input, output).with_config(self.config);
+        runner.run_bytecode(&self.bytecode())
+    }
+
+    /// Runs the optimized IR directly, without encoding it as bytecode.
+    pub fn run_ir(&self) -> Result<(), Error> {
+        self.run_ir_with(io::stdin(), io::stdout())
+    }
+
+    /// Runs the optimized IR directly with the given streams.
+    pub fn run_ir_with<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), Error> {
+        let mut runner = Runner::with_io(vec![], vec![], input, output).with_config(self.config);
+        runner.run_ins(&self.optimize())
+    }
+
+    /// Runs the program token by token, without optimizing it first.
+    pub fn run_tokens(&self) -> Result<(), Error> {
+        self.run_tokens_with(io::stdin(), io::stdout())
+    }
+
+    /// Runs the program token by token with the given streams.
+    pub fn run_tokens_with<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), Error> {
+        let mut runner = Runner::with_io(self.tokens.clone(), self.jumps.clone(), input, output)
+            .with_config(self.config);
+        runner.run()
+    }
+
+    /// Compiles the program to x86-64 machine code in memory and runs it on
+    /// stdin and stdout, much faster than the interpreters.
+    ///
+    /// Only x86-64 Linux has a JIT; elsewhere this fails with
+    /// [`CodegenError::Unsupported`].
+    pub fn run_jit(&self) -> Result<(), Error> {
+        self.run_jit_with(io::stdin(), io::stdout())
+    }
+
+    /// Same as [`run_jit`](Self::run_jit), with the given streams.
+    pub fn run_jit_with<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), Error> {
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        return JitCompiler::with_config(self.config)
+            .compile(&self.optimize())?
+            .run_with(input, output);
+        #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+        {
+            let _ = (input, output);
+            Err(CodegenError::Unsupported("The JIT only runs on x86-64 Linux".to_string()).into())
+        }
+    }
+
+    /// Generates FASM source for a Linux executable, for the target in the
+    /// config.
+    ///
+    /// Fails for options the generated code can't honour, such as unbounded
+    /// cells.
+    pub fn assembly(&self) -> Result<String, Error> {
+        Ok(FasmGenerator::with_config(self.config).generate(&self.optimize())?)
+    }
+
+    /// Same as [`assembly`](Self::assembly), for another assembler.
+    pub fn assembly_in(&self, dialect: Dialect) -> Result<String, Error> {
+        let asm = FasmGenerator::with_config(self.config).lower(&self.optimize())?;
+        Ok(dialect.render(&asm))
+    }
+
+    /// Compiles the program to a static Linux executable for the target in
+    /// the config, the same code [`assembly`](Self::assembly) lists.
+    pub fn executable(&self) -> Result<Vec<u8>, Error> {
+        let asm = FasmGenerator::with_config(self.config).lower(&self.optimize())?;
+        Ok(elf::executable(&asm)?)
+    }
+
+    /// Compiles the program to a binary WebAssembly module that runs under
+    /// WASI, exporting its memory and `_start`.
+    pub fn wasm(&self) -> Result<Vec<u8>, Error> {
+        Ok(WasmGenerator::with_config(self.config).binary(&self.optimize())?)
+    }
+
+    /// Same as [`wasm`](Self::wasm), in the WebAssembly text format.
+    pub fn wat(&self) -> Result<String, Error> {
+        Ok(WasmGenerator::with_config(self.config).generate(&self.optimize())?)
+    }
+
+    /// Translates the program to C, with the cell width, tape length and
+    /// policies in the config built in.
+    pub fn c_source(&self) -> Result<String, Error> {
+        Ok(CGenerator::with_config(self.config).generate(&self.optimize())?)
+    }
+
+    /// Translates the program to a Rust `main.rs`, with the cell width, tape
+    /// length and policies in the config built in. See
+    /// [`rustgen::manifest`] for building it as a crate.
+    pub fn rust_source(&self) -> Result<String, Error> {
+        Ok(RustGenerator::with_config(self.config).generate(&self.optimize())?)
+    }
+
+    /// Translates the program to an ES module exporting `run`, which takes
+    /// an input provider and an output callback.
+    ///
+    /// ```
+    /// let program = brainfuck::Program::parse(",+.").unwrap();
+    /// let js = program.js_source().unwrap();
+    /// assert!(js.contains("export function run({ input = null, output = null } = {}) {"));
+    /// ```
+    pub fn js_source(&self) -> Result<String, Error> {
+        Ok(JsGenerator::with_config(self.config).generate(&self.optimize())?)
+    }
+}
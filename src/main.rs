@@ -1,40 +1,258 @@
-mod exe;
-mod fasm;
-mod ir;
-mod lex;
-mod parse;
+use brainfuck::{
+    rustgen, x86, BoundsPolicy, Bytecode, CellWidth, CodegenError, Config, Diagnostic, Dialect,
+    EofPolicy, Error, LexMode, Lexer, OverflowPolicy, Program, Representation, Runner,
+    SyntaxParser, Target,
+};
 use colored::Colorize;
-use exe::Runner;
-use fasm::FasmGenerator;
-use ir::Representation;
-use lex::Lexer;
-use parse::SyntaxParser;
-use std::{fs::read_to_string, io::Write, process::exit};
+use std::{
+    fs::read_to_string,
+    io::Write,
+    process::{exit, Command},
+};
+
+/// Which interpreter executes the program.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    /// Walks the tokens one by one
+    Tokens,
+    /// Runs the optimized IR
+    Ir,
+    /// Runs the IR encoded as bytecode
+    Bytecode,
+    /// Compiles the IR to machine code in memory and runs that
+    Jit,
+}
+
+/// What turns the generated code into an executable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Assembler {
+    /// The compiler's own encoder
+    Builtin,
+    /// An external assembler, and `ld` unless it's FASM
+    External(Dialect),
+    /// The first external one that is installed, or the built-in one
+    Auto,
+}
+
+/// What --compile turns the program into.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// An executable for the target in the config
+    Native,
+    /// A WebAssembly module for WASI
+    Wasm,
+    /// The same module in the text format
+    Wat,
+    /// C source, for any C compiler to build
+    C,
+    /// A Rust source file, for `rustc` to build
+    Rust,
+    /// A Cargo project with the Rust source as its binary
+    RustCrate,
+    /// An ES module, for web pages and Node
+    Js,
+}
 
 fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    let mut file: Option<&String> = None;
+    let mut compile = false;
+    let mut listing = false;
+    let mut assembler = Assembler::Builtin;
+    let mut output = Output::Native;
+    let mut targeted = false;
+    let mut engine = Engine::Bytecode;
+    let mut mode = LexMode::default();
+    let mut config = Config::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                help();
+                return;
+            }
+            "-c" | "--compile" => compile = true,
+            "--asm" => listing = true,
+            "--assembler" => {
+                assembler = match iter.next().map(|s| s.as_str()) {
+                    Some("builtin") => Assembler::Builtin,
+                    Some("fasm") => Assembler::External(Dialect::Fasm),
+                    Some("nasm") => Assembler::External(Dialect::Nasm),
+                    Some("gas") => Assembler::External(Dialect::GasIntel),
+                    Some("gas-att") => Assembler::External(Dialect::GasAtt),
+                    Some("auto") => Assembler::Auto,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `builtin`, `fasm`, `nasm`, `gas`, `gas-att` or `auto` after --assembler".red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "-e" | "--engine" => {
+                engine = match iter.next().map(|s| s.as_str()) {
+                    Some("bytecode") => Engine::Bytecode,
+                    Some("ir") => Engine::Ir,
+                    Some("tokens") => Engine::Tokens,
+                    Some("jit") => Engine::Jit,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `bytecode`, `ir`, `tokens` or `jit` after --engine".red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--jit" => engine = Engine::Jit,
+            "--lexer" => {
+                mode = match iter.next().map(|s| s.as_str()) {
+                    Some("standard") => LexMode::Standard,
+                    Some("comments") => LexMode::Comments,
+                    Some("strict") => LexMode::Strict,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `standard`, `comments` or `strict` after --lexer".red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--eof" => {
+                config.eof = match iter.next().map(|s| s.as_str()) {
+                    Some("unchanged") => EofPolicy::Unchanged,
+                    Some("zero") => EofPolicy::Zero,
+                    Some("max") => EofPolicy::Max,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `unchanged`, `zero` or `max` after --eof".red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--cell" => {
+                config.cell = match iter.next().map(|s| s.as_str()) {
+                    Some("8") => CellWidth::U8,
+                    Some("16") => CellWidth::U16,
+                    Some("32") => CellWidth::U32,
+                    Some("unbounded") => CellWidth::Unbounded,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `8`, `16`, `32` or `unbounded` after --cell".red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--tape" => {
+                config.tape_len = match iter.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(len)) if len > 0 => len,
+                    _ => {
+                        eprintln!("{}", "Expected a number of cells after --tape".red());
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--bounds" => {
+                config.bounds = match iter.next().map(|s| s.as_str()) {
+                    Some("wrap") => BoundsPolicy::Wrap,
+                    Some("trap") => BoundsPolicy::Trap,
+                    Some("grow") => BoundsPolicy::Grow,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `wrap`, `trap` or `grow` after --bounds".red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--grow" => config.bounds = BoundsPolicy::Grow,
+            "--no-color" => colored::control::set_override(false),
+            "--overflow" => {
+                config.overflow = match iter.next().map(|s| s.as_str()) {
+                    Some("wrap") => OverflowPolicy::Wrap,
+                    Some("trap") => OverflowPolicy::Trap,
+                    _ => {
+                        eprintln!("{}", "Expected `wrap` or `trap` after --overflow".red());
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            "--target" => {
+                targeted = true;
+                match iter.next().map(|s| s.as_str()) {
+                    Some("x86") => (output, config.target) = (Output::Native, Target::X86),
+                    Some("x86_64") => (output, config.target) = (Output::Native, Target::X86_64),
+                    Some("wasm") => output = Output::Wasm,
+                    Some("wat") => output = Output::Wat,
+                    Some("c") => output = Output::C,
+                    Some("rust") => output = Output::Rust,
+                    Some("rust-crate") => output = Output::RustCrate,
+                    Some("js") => output = Output::Js,
+                    _ => {
+                        eprintln!(
+                            "{}",
+                            "Expected `x86`, `x86_64`, `wasm`, `wat`, `c`, `rust`, `rust-crate` or `js` after --target"
+                                .red()
+                        );
+                        help();
+                        exit(1);
+                    }
+                }
+            }
+            _ if file.is_none() => file = Some(arg),
+            _ => {
+                eprintln!("{}", "Too many arguments".red());
+                help();
+                exit(1);
+            }
+        }
+    }
 
-    if args.len() == 1 {
-        run_prompt();
-    } else if args.len() > 3 {
-        eprintln!("{}", "Too many arguments".red());
+    if targeted && !compile {
+        eprintln!("{}", "--target only applies to --compile".red());
         help();
         exit(1);
-    } else if args.len() == 2 {
-        if args[1] == "-h" || args[1] == "--help" {
+    }
+
+    match file {
+        None if compile => {
+            eprintln!("{}", "No file to compile".red());
             help();
-        } else {
-            run_file(&args[1]);
+            exit(1);
         }
-    } else if args[1] == "-c" || args[1] == "--compile" {
-        match compile_file(&args[2]) {
-            Ok(_) => println!("{}", "Compilation successful".green()),
-            Err(e) => eprintln!("{}: {}", "Compilation failed".red(), e),
+        None => run_prompt(engine, mode, config),
+        Some(file) if compile => {
+            let content = read_source(file);
+            match compile_file(file, &content, mode, config, output, assembler, listing) {
+                Ok(_) => println!("{}", "Compilation successful".green()),
+                Err(e) => {
+                    eprint!("{}", Diagnostic::new(&e, file, &content));
+                    exit(1);
+                }
+            }
         }
+        Some(file) => run_file(file, engine, mode, config),
     }
 }
 
-fn run_file(file: &String) {
+fn read_source(file: &String) -> String {
     if !file.ends_with(".bf") {
         eprintln!("{}", "The extension of the file should be .bf".red());
         exit(1);
@@ -50,39 +268,34 @@ fn run_file(file: &String) {
             exit(1)
         }
     }
+    content
+}
 
-    let mut lexer = Lexer::new(content);
-
-    match lexer.parse() {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("{}", e.red());
-            exit(1);
-        }
-    }
-
-    let mut syntax = SyntaxParser::new();
+fn run_file(file: &String, engine: Engine, mode: LexMode, config: Config) {
+    let content = read_source(file);
 
-    match syntax.parse(lexer.tokens()) {
-        Ok(_) => {}
+    let program = match Program::parse_with(&content, mode) {
+        Ok(program) => program.with_config(config),
         Err(e) => {
-            eprintln!("{}", e.red());
+            eprint!("{}", Diagnostic::new(&e, file, &content));
             exit(1);
         }
-    }
+    };
 
-    let mut rep = Representation::new();
-    rep.parse(lexer.tokens());
+    let result = match engine {
+        Engine::Tokens => program.run_tokens(),
+        Engine::Bytecode => program.run(),
+        Engine::Ir => program.run_ir(),
+        Engine::Jit => program.run_jit(),
+    };
 
-    for ins in &rep.instructions {
-        println!("{:?}", ins);
+    if let Err(e) = result {
+        eprint!("{}", Diagnostic::new(&e, file, &content));
+        exit(1);
     }
-
-    let mut runner = Runner::new(lexer.tokens().clone());
-    runner.run();
 }
 
-fn run_prompt() {
+fn run_prompt(engine: Engine, mode: LexMode, config: Config) {
     println!("{}", "brainfuck interpreter".yellow());
     println!("Type {} to quit", "exit".red());
     match std::io::stdout().flush() {
@@ -91,7 +304,7 @@ fn run_prompt() {
             eprintln!("{}", "Something went wrong".red());
         }
     }
-    let mut runner = Runner::new(vec![]);
+    let mut runner = Runner::new(vec![], vec![]).with_config(config);
 
     loop {
         print!("{} ", ">>>".green());
@@ -105,6 +318,7 @@ fn run_prompt() {
         let mut input = "".into();
 
         match std::io::stdin().read_line(&mut input) {
+            Ok(0) => exit(0), // end of input
             Ok(_) => {}
             _ => {
                 eprintln!("{}", "Unable to read the input".red());
@@ -135,12 +349,12 @@ fn run_prompt() {
             exit(0);
         }
 
-        let mut lexer = Lexer::new(input);
+        let mut lexer = Lexer::new(input.clone()).with_mode(mode);
 
         match lexer.parse() {
             Ok(_) => {}
             Err(e) => {
-                eprintln!("{}", e.red());
+                eprint!("{}", Diagnostic::new(&Error::many(e), "<prompt>", &input));
                 continue;
             }
         }
@@ -150,12 +364,32 @@ fn run_prompt() {
         match syntax.parse(lexer.tokens()) {
             Ok(_) => {}
             Err(e) => {
-                eprintln!("{}", e.red());
+                eprint!("{}", Diagnostic::new(&Error::many(e), "<prompt>", &input));
                 continue;
             }
         }
-        runner.add(&mut lexer.tokens().clone());
-        runner.run();
+        let result = match engine {
+            Engine::Tokens => {
+                runner.add(&mut lexer.tokens().clone(), syntax.jumps());
+                runner.run()
+            }
+            Engine::Ir => {
+                let mut rep = Representation::with_config(config);
+                rep.parse(lexer.tokens());
+                runner.run_ins(&rep)
+            }
+            // The tape carries over from line to line, which only the
+            // interpreters can do.
+            Engine::Bytecode | Engine::Jit => {
+                let mut rep = Representation::with_config(config);
+                rep.parse(lexer.tokens());
+                runner.run_bytecode(&Bytecode::new(&rep))
+            }
+        };
+
+        if let Err(e) = result {
+            eprint!("{}", Diagnostic::new(&e, "<prompt>", &input));
+        }
         println!();
     }
 }
@@ -174,61 +408,204 @@ fn help() {
         "{}    Compiles the source code",
         "-c, --compile <file>.bf".yellow()
     );
+    println!(
+        "{}                      Keeps the assembly next to the executable when compiling",
+        "--asm".yellow()
+    );
+    println!(
+        "{}\n                           What --compile assembles with (default: builtin)",
+        "--assembler <builtin|fasm|nasm|gas|gas-att|auto>".yellow()
+    );
+    println!(
+        "{}\n                           Picks the interpreter, or the x86-64 Linux JIT\n                           (default: bytecode)",
+        "-e, --engine <bytecode|ir|tokens|jit>".yellow()
+    );
+    println!(
+        "{}                      Same as --engine jit",
+        "--jit".yellow()
+    );
+    println!(
+        "{}\n                           What other characters are: ignored, `//` comments\n                           or errors (default: comments)",
+        "--lexer <standard|comments|strict>".yellow()
+    );
+    println!(
+        "{}\n                           What `,` stores at end of input (default: unchanged)",
+        "--eof <unchanged|zero|max>".yellow()
+    );
+    println!(
+        "{}\n                           Bits per cell (default: 8)",
+        "--cell <8|16|32|unbounded>".yellow()
+    );
+    println!(
+        "{}             Cells on the tape (default: 30000)",
+        "--tape <cells>".yellow()
+    );
+    println!(
+        "{}\n                           What moving off the tape does (default: wrap)",
+        "--bounds <wrap|trap|grow>".yellow()
+    );
+    println!(
+        "{}                     Same as --bounds grow",
+        "--grow".yellow()
+    );
+    println!(
+        "{}\n                           What a cell going out of range does (default: wrap)",
+        "--overflow <wrap|trap>".yellow()
+    );
+    println!(
+        "{}\n                           What --compile builds for; `wasm` is a module for\n                           WASI, `wat`, `c`, `rust` and `js` write source\n                           instead, and `rust-crate` a Cargo project\n                           (default: x86)",
+        "--target <x86|x86_64|wasm|wat|c|rust|rust-crate|js>".yellow()
+    );
+    println!(
+        "{}                 Prints errors without colours",
+        "--no-color".yellow()
+    );
     println!("{}                 Prints this message", "-h, --help".yellow());
 }
 
-fn compile_file(file: &String) -> Result<(), String> {
-    if !file.ends_with(".bf") {
-        return Err("File must have .bf extension".to_string());
+fn compile_file(
+    file: &str,
+    content: &str,
+    mode: LexMode,
+    config: Config,
+    output: Output,
+    assembler: Assembler,
+    listing: bool,
+) -> Result<(), Error> {
+    let program = Program::parse_with(content, mode)?.with_config(config);
+
+    match output {
+        Output::Native => {}
+        Output::Wasm => {
+            if listing {
+                std::fs::write(file.replace(".bf", ".wat"), program.wat()?)?;
+            }
+            return Ok(std::fs::write(
+                file.replace(".bf", ".wasm"),
+                program.wasm()?,
+            )?);
+        }
+        Output::Wat => return Ok(std::fs::write(file.replace(".bf", ".wat"), program.wat()?)?),
+        Output::C => {
+            return Ok(std::fs::write(
+                file.replace(".bf", ".c"),
+                program.c_source()?,
+            )?)
+        }
+        Output::Rust => {
+            return Ok(std::fs::write(
+                file.replace(".bf", ".rs"),
+                program.rust_source()?,
+            )?)
+        }
+        Output::RustCrate => {
+            // A directory named after the program, where the executable would go
+            let dir = std::path::PathBuf::from(file.replace(".bf", ""));
+            let name = dir
+                .file_name()
+                .map_or("brainfuck".into(), |n| n.to_string_lossy());
+            let source = program.rust_source()?;
+            std::fs::create_dir_all(dir.join("src"))?;
+            std::fs::write(dir.join("Cargo.toml"), rustgen::manifest(&name))?;
+            std::fs::write(dir.join("src").join("main.rs"), source)?;
+            return Ok(());
+        }
+        Output::Js => {
+            return Ok(std::fs::write(
+                file.replace(".bf", ".mjs"),
+                program.js_source()?,
+            )?)
+        }
     }
 
-    let content =
-        std::fs::read_to_string(file).map_err(|e| format!("Failed to read file: {}", e))?;
-
-    let mut lexer = Lexer::new(content);
-    lexer.parse().map_err(|e| format!("Lexer error: {}", e))?;
-
-    let mut syntax = SyntaxParser::new();
-    syntax
-        .parse(lexer.tokens())
-        .map_err(|e| format!("Parser error: {}", e))?;
-
-    let mut rep = Representation::new();
-    rep.parse(lexer.tokens());
-
-    let mut gen = FasmGenerator::new();
-    let asm = gen.generate(&rep.instructions);
-
-    let output_asm = file.replace(".bf", ".asm");
-    std::fs::write(&output_asm, asm).map_err(|e| format!("Failed to write ASM file: {}", e))?;
+    let assembler = match assembler {
+        Assembler::Auto => [Dialect::Fasm, Dialect::Nasm, Dialect::GasIntel]
+            .into_iter()
+            .find(|dialect| tools(*dialect).iter().all(|tool| installed(tool)))
+            .map_or(Assembler::Builtin, Assembler::External),
+        assembler => assembler,
+    };
 
     // Output executable name will be the same as the input file but without extension
     let output_exe = file.replace(".bf", "");
 
-    // Run FASM to compile directly to an executable
-    let status = std::process::Command::new("fasm")
-        .args([&output_asm, &output_exe])
-        .status()
-        .map_err(|e| format!("Failed to run FASM: {}", e))?;
-
-    if !status.success() {
-        return Err("FASM compilation failed".to_string());
+    match assembler {
+        Assembler::External(dialect) => {
+            let source = file.replace(".bf", &format!(".{}", dialect.extension()));
+            std::fs::write(&source, program.assembly_in(dialect)?)?;
+            let result = assemble(dialect, &source, &output_exe, config);
+            if !listing {
+                std::fs::remove_file(&source)?;
+            }
+            result?;
+        }
+        _ => {
+            if listing {
+                std::fs::write(file.replace(".bf", ".asm"), program.assembly()?)?;
+            }
+            std::fs::write(&output_exe, program.executable()?)?;
+        }
     }
 
     // Make the output file executable
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
-        let metadata = std::fs::metadata(&output_exe)
-            .map_err(|e| format!("Failed to get file metadata: {}", e))?;
+        let metadata = std::fs::metadata(&output_exe)?;
         let mut perms = metadata.permissions();
         perms.set_mode(0o755); // rwxr-xr-x
-        std::fs::set_permissions(&output_exe, perms)
-            .map_err(|e| format!("Failed to set file permissions: {}", e))?;
+        std::fs::set_permissions(&output_exe, perms)?;
+    }
+
+    Ok(())
+}
+
+// The programs that turn source in `dialect` into an executable.
+fn tools(dialect: Dialect) -> &'static [&'static str] {
+    match dialect {
+        Dialect::Fasm => &["fasm"],
+        Dialect::Nasm => &["nasm", "ld"],
+        Dialect::GasIntel | Dialect::GasAtt => &["as", "ld"],
+    }
+}
+
+fn installed(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok()
+}
+
+// Assembles `source` and links it into `exe`.
+fn assemble(dialect: Dialect, source: &str, exe: &str, config: Config) -> Result<(), Error> {
+    let x86_64 = config.target == Target::X86_64;
+    let object = format!("{}.o", exe);
+    match dialect {
+        Dialect::Fasm => return run_tool("fasm", &[source, exe]),
+        Dialect::Nasm => {
+            let format = if x86_64 { "elf64" } else { "elf32" };
+            run_tool("nasm", &["-f", format, source, "-o", &object])?;
+        }
+        Dialect::GasIntel | Dialect::GasAtt => {
+            let bits = if x86_64 { "--64" } else { "--32" };
+            run_tool("as", &[bits, source, "-o", &object])?;
+        }
     }
 
-    // Delete the generated .asm file now that compilation was successful.
-    std::fs::remove_file(&output_asm).map_err(|e| format!("Failed to remove ASM file: {}", e))?;
+    let emulation = if x86_64 { "elf_x86_64" } else { "elf_i386" };
+    let result = run_tool(
+        "ld",
+        &["-m", emulation, "-e", x86::ENTRY, &object, "-o", exe],
+    );
+    std::fs::remove_file(&object)?;
+    result
+}
 
+fn run_tool(tool: &str, args: &[&str]) -> Result<(), Error> {
+    let status = Command::new(tool)
+        .args(args)
+        .status()
+        .map_err(|e| CodegenError::Assembler(format!("couldn't run {}: {}", tool, e)))?;
+
+    if !status.success() {
+        return Err(CodegenError::Assembler(format!("{} exited with {}", tool, status)).into());
+    }
     Ok(())
 }
@@ -1,38 +1,68 @@
+use crate::{
+    error::SyntaxError,
+    lex::{Token, TokenType},
+};
 
-use crate::lex::{Token, TokenType};
-
+/// Checks that loops are balanced and pairs up their brackets.
 pub struct SyntaxParser {
-    stack: Vec<Token>,
+    stack: Vec<(usize, Token)>,
+    jumps: Vec<usize>,
+}
+
+impl Default for SyntaxParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SyntaxParser {
     pub fn new() -> Self {
         Self {
             stack: Vec::new(),
+            jumps: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self, tokens: &Vec<Token>) -> Result<(), String> {
-        for token in tokens.iter() {
-            
+    /// Pairs up the brackets in `tokens`, reporting every one that can't be
+    /// paired, in source order.
+    pub fn parse(&mut self, tokens: &[Token]) -> Result<(), Vec<SyntaxError>> {
+        self.jumps = vec![0; tokens.len()];
+        let mut errors = vec![];
+
+        for (i, token) in tokens.iter().enumerate() {
             match token.token_type() {
                 TokenType::LoopStart => {
-                    self.stack.push(token.clone());
+                    self.stack.push((i, *token));
                 }
                 TokenType::LoopEnd => {
-                    if self.stack.is_empty() {
-                        return Err("Syntax Error: Unmatched LoopEnd".to_string());
-                    }
-                    self.stack.pop();
+                    let Some((start, _)) = self.stack.pop() else {
+                        errors.push(SyntaxError::UnmatchedLoopEnd { span: token.span() });
+                        continue;
+                    };
+                    self.jumps[start] = i;
+                    self.jumps[i] = start;
                 }
                 _ => {}
             }
         }
 
-        if !self.stack.is_empty() {
-            return Err("Syntax Error: Unmatched LoopStart".to_string());
+        errors.extend(
+            self.stack
+                .drain(..)
+                .map(|(_, token)| SyntaxError::UnmatchedLoopStart { span: token.span() }),
+        );
+        errors.sort_by_key(|e| e.span().pos);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
+    }
 
-        Ok(())
+    /// For every `[` and `]`, the index of its matching bracket in the tokens
+    /// passed to `parse`. Other entries are unused.
+    pub fn jumps(&self) -> &Vec<usize> {
+        &self.jumps
     }
-}
\ No newline at end of file
+}
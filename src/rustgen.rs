@@ -0,0 +1,311 @@
+//! Translates the IR to a standalone Rust program.
+//!
+//! The tape is a `Vec` that is only ever indexed, so the translation needs
+//! no `unsafe`, and output goes through a `BufWriter` that is flushed before
+//! each read. The program builds with a plain `rustc -O`, or as a crate with
+//! the [`manifest`] next to it.
+
+use crate::{
+    config::{BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy},
+    error::CodegenError,
+    ir::{Helpers, Instruction, Representation},
+};
+
+/// Generates the `main.rs` of a Rust program from the IR.
+pub struct RustGenerator {
+    config: Config,
+}
+
+impl Default for RustGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RustGenerator {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self { config }
+    }
+
+    pub fn generate(&self, rep: &Representation) -> Result<String, CodegenError> {
+        let cell = match self.config.cell {
+            CellWidth::U8 => "u8",
+            CellWidth::U16 => "u16",
+            CellWidth::U32 => "u32",
+            CellWidth::Unbounded => {
+                return Err(CodegenError::Unsupported(
+                    "Unbounded cells are not supported by the Rust backend".to_string(),
+                ))
+            }
+        };
+        let used = Helpers::new(rep, self.config);
+        let traps = used.traps();
+
+        let mut out = Vec::new();
+        out.push(if used.reads {
+            "use std::io::{self, BufWriter, Read, Write};"
+        } else {
+            "use std::io::{self, BufWriter, Write};"
+        });
+        if traps {
+            out.push("use std::process::exit;");
+        }
+        out.push("");
+        let tape_len = format!("const TAPE_LEN: usize = {};", self.config.tape_len.max(1));
+        let cell = format!("type Cell = {};", cell);
+        out.push(&tape_len);
+        out.push(&cell);
+        out.push("");
+
+        if rep.instructions.is_empty() {
+            out.push("#[allow(dead_code)] // nothing uses the tape");
+        }
+        out.push("struct Machine {");
+        out.push("    tape: Vec<Cell>,");
+        out.push("    p: usize,");
+        if used.origin {
+            out.push("    /// Where cell 0 is now");
+            out.push("    origin: usize,");
+        }
+        if used.reads {
+            out.push("    input: io::StdinLock<'static>,");
+        }
+        out.push("    output: BufWriter<io::StdoutLock<'static>>,");
+        out.push("}");
+        out.push("");
+
+        let methods = self.methods(&used, traps);
+        out.push("impl Machine {");
+        out.push(&methods);
+        out.push("}");
+        out.push("");
+
+        out.push("fn main() -> io::Result<()> {");
+        out.push("    let mut m = Machine {");
+        out.push("        tape: vec![0; TAPE_LEN],");
+        out.push("        p: 0,");
+        if used.origin {
+            out.push("        origin: 0,");
+        }
+        if used.reads {
+            out.push("        input: io::stdin().lock(),");
+        }
+        out.push("        output: BufWriter::new(io::stdout().lock()),");
+        out.push("    };");
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
+            if *ins == Instruction::LoopEnd {
+                depth -= 1;
+            }
+            let at = used.at_arg(ins, *span);
+            let line = match ins {
+                Instruction::Add { offset, delta } => {
+                    format!("m.add({}, {}{});", offset, delta, at)
+                }
+                Instruction::Move(n) => format!("m.shift({}{});", n, at),
+                Instruction::Print { offset } => format!("m.print({}{})?;", offset, at),
+                Instruction::Read { offset } => format!("m.read({}{})?;", offset, at),
+                Instruction::LoopStart => "while m.tape[m.p] != 0 {".to_string(),
+                Instruction::LoopEnd => "}".to_string(),
+            };
+            body.push(format!("{}{}", "    ".repeat(depth), line));
+            if *ins == Instruction::LoopStart {
+                depth += 1;
+            }
+        }
+        let body = body.join("\n");
+        if !body.is_empty() {
+            out.push(&body);
+        }
+
+        out.push("    m.output.flush()");
+        out.push("}");
+        out.push("");
+        Ok(out.join("\n"))
+    }
+
+    // The methods of `Machine` that are called, for the options in the
+    // config. Ones that aren't are left out, so rustc doesn't warn about
+    // them. `$AT` stands for the parameter naming the code to blame, and
+    // `$PASS` for passing it on to `seek`.
+    fn methods(&self, used: &Helpers, traps: bool) -> String {
+        let mut methods = Vec::new();
+
+        if used.seeks() {
+            methods.push(match self.config.bounds {
+                BoundsPolicy::Wrap => {
+                    "    /// Index of the cell `offset` away from the current one, coming back
+    /// in on the other end of the tape.
+    fn seek(&mut self, offset: isize) -> usize {
+        let i = self.p as isize + offset;
+        if (0..TAPE_LEN as isize).contains(&i) {
+            i as usize
+        } else {
+            i.rem_euclid(TAPE_LEN as isize) as usize
+        }
+    }"
+                    .to_string()
+                }
+                BoundsPolicy::Trap => {
+                    "    /// Index of the cell `offset` away from the current one, stopping the
+    /// program if it is off the tape.
+    fn seek(&mut self, offset: isize, at: &str) -> usize {
+        let i = self.p as isize + offset;
+        if !(0..TAPE_LEN as isize).contains(&i) {
+            self.fail(format!(
+                \"Pointer out of bounds at {}: cell {} is not on the tape of {} cells\",
+                at, i, TAPE_LEN
+            ));
+        }
+        i as usize
+    }"
+                    .to_string()
+                }
+                BoundsPolicy::Grow => {
+                    let origin = if used.origin {
+                        "\n            self.origin += before;"
+                    } else {
+                        ""
+                    };
+                    format!(
+                        "    /// Index of the cell `offset` away from the current one, growing the
+    /// tape to reach it, at least doubling it each time.
+    fn seek(&mut self, offset: isize) -> usize {{
+        let i = self.p as isize + offset;
+        let len = self.tape.len();
+        if i < 0 {{
+            let before = i.unsigned_abs().max(len);
+            self.tape.splice(0..0, std::iter::repeat(0).take(before));
+            self.p += before;{}
+            return (i + before as isize) as usize;
+        }}
+        if i as usize >= len {{
+            self.tape.resize(len + (i as usize + 1 - len).max(len), 0);
+        }}
+        i as usize
+    }}",
+                        origin
+                    )
+                }
+            });
+        }
+
+        if used.moves {
+            methods.push(
+                "    fn shift(&mut self, offset: isize$AT) {
+        self.p = self.seek(offset$PASS);
+    }"
+                .to_string(),
+            );
+        }
+
+        if used.adds {
+            let add = if self.config.overflow == OverflowPolicy::Trap {
+                let origin = if used.origin {
+                    " - self.origin as isize"
+                } else {
+                    ""
+                };
+                format!(
+                    "    /// Adds `delta` to the cell, stopping the program if it goes below 0 or
+    /// past `Cell::MAX`.
+    fn add(&mut self, offset: isize, delta: i32$AT) {{
+        let i = self.seek(offset$PASS);
+        let value = self.tape[i] as i64 + delta as i64;
+        if value < 0 || value > Cell::MAX as i64 {{
+            let cell = i as isize{};
+            let went = if value < 0 {{ \"below 0\" }} else {{ \"past its largest value\" }};
+            self.fail(format!(\"Cell overflow at {{}}: cell {{}} went {{}}\", at, cell, went));
+        }}
+        self.tape[i] = value as Cell;
+    }}",
+                    origin
+                )
+            } else {
+                "    fn add(&mut self, offset: isize, delta: i32$AT) {
+        let i = self.seek(offset$PASS);
+        self.tape[i] = self.tape[i].wrapping_add(delta as Cell);
+    }"
+                .to_string()
+            };
+            methods.push(used.fill(&add, used.add_at(), ", at: &str"));
+        }
+
+        if used.prints {
+            methods.push(
+                "    fn print(&mut self, offset: isize$AT) -> io::Result<()> {
+        let i = self.seek(offset$PASS);
+        self.output.write_all(&[self.tape[i] as u8])
+    }"
+                .to_string(),
+            );
+        }
+
+        if used.reads {
+            let eof = match self.config.eof {
+                EofPolicy::Unchanged => "{}",
+                EofPolicy::Zero => "self.tape[i] = 0",
+                EofPolicy::Max => "self.tape[i] = Cell::MAX",
+            };
+            methods.push(format!(
+                "    /// Reads a byte into the cell, flushing first so prompts show up.
+    fn read(&mut self, offset: isize$AT) -> io::Result<()> {{
+        let i = self.seek(offset$PASS);
+        self.output.flush()?;
+        let mut byte = [0];
+        match self.input.read_exact(&mut byte) {{
+            Ok(()) => self.tape[i] = byte[0] as Cell,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+            Err(e) => return Err(e),
+        }}
+        Ok(())
+    }}",
+                eof
+            ));
+        }
+
+        if traps {
+            methods.push(
+                "    /// Stops the program with a runtime error.
+    fn fail(&mut self, message: String) -> ! {
+        let _ = self.output.flush();
+        eprintln!(\"Runtime error: {}\", message);
+        exit(1);
+    }"
+                .to_string(),
+            );
+        }
+
+        let methods: Vec<String> = methods
+            .iter()
+            .map(|method| used.fill(method, used.seek_at(), ", at: &str"))
+            .collect();
+        methods.join("\n\n")
+    }
+}
+
+/// The manifest of a crate with a program from [`RustGenerator`] as its
+/// `src/main.rs`. `name` is cleaned up to be a valid package name.
+pub fn manifest(name: &str) -> String {
+    let mut package: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '-' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .collect();
+    if !package.starts_with(|c: char| c.is_ascii_lowercase()) {
+        package.insert_str(0, "bf-");
+    }
+    format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n",
+        package
+    )
+}
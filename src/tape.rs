@@ -0,0 +1,276 @@
+use std::fmt;
+
+use crate::config::CellWidth;
+
+/// The interpreters' memory: a row of cells of the configured width.
+///
+/// Bounded cells wrap around on overflow and live in a flat `Vec<u32>`;
+/// unbounded ones are arbitrary precision integers.
+pub(crate) struct Tape {
+    cells: Cells,
+}
+
+enum Cells {
+    Bounded { cells: Vec<u32>, mask: u32 },
+    Unbounded(Vec<BigCell>),
+}
+
+impl Tape {
+    pub fn new(len: usize, width: CellWidth) -> Self {
+        let len = len.max(1);
+        let cells = match width {
+            CellWidth::U8 => Cells::Bounded {
+                cells: vec![0; len],
+                mask: u8::MAX as u32,
+            },
+            CellWidth::U16 => Cells::Bounded {
+                cells: vec![0; len],
+                mask: u16::MAX as u32,
+            },
+            CellWidth::U32 => Cells::Bounded {
+                cells: vec![0; len],
+                mask: u32::MAX,
+            },
+            CellWidth::Unbounded => Cells::Unbounded(vec![BigCell::default(); len]),
+        };
+        Self { cells }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        match &self.cells {
+            Cells::Bounded { cells, .. } => cells.len(),
+            Cells::Unbounded(cells) => cells.len(),
+        }
+    }
+
+    /// The cells and the largest value they hold, unless they are unbounded.
+    #[inline]
+    pub fn bounded_mut(&mut self) -> Option<(&mut [u32], u32)> {
+        match &mut self.cells {
+            Cells::Bounded { cells, mask } => Some((cells, *mask)),
+            Cells::Unbounded(_) => None,
+        }
+    }
+
+    /// Adds at least `min` cells in front of the tape, returning how many were
+    /// added; existing cells move up by that much. Grows geometrically so
+    /// repeated calls stay cheap.
+    pub fn grow_left(&mut self, min: usize) -> usize {
+        let extra = min.max(self.len());
+        match &mut self.cells {
+            Cells::Bounded { cells, .. } => {
+                cells.splice(0..0, std::iter::repeat_n(0, extra));
+            }
+            Cells::Unbounded(cells) => {
+                cells.splice(0..0, std::iter::repeat_n(BigCell::default(), extra));
+            }
+        }
+        extra
+    }
+
+    /// Makes room for at least `min_len` cells, adding them at the end.
+    pub fn grow_right(&mut self, min_len: usize) {
+        let len = min_len.max(self.len() * 2);
+        match &mut self.cells {
+            Cells::Bounded { cells, .. } => cells.resize(len, 0),
+            Cells::Unbounded(cells) => cells.resize(len, BigCell::default()),
+        }
+    }
+
+    #[inline]
+    pub fn is_zero(&self, i: usize) -> bool {
+        match &self.cells {
+            Cells::Bounded { cells, .. } => cells[i] == 0,
+            Cells::Unbounded(cells) => cells[i].is_zero(),
+        }
+    }
+
+    /// Adds `delta` to a cell, returning false if it had to wrap around.
+    #[inline]
+    pub fn add(&mut self, i: usize, delta: i32) -> bool {
+        match &mut self.cells {
+            Cells::Bounded { cells, mask } => {
+                let sum = cells[i] as i64 + delta as i64;
+                cells[i] = sum as u32 & *mask;
+                (0..=*mask as i64).contains(&sum)
+            }
+            Cells::Unbounded(cells) => {
+                cells[i].add(delta as i64);
+                true
+            }
+        }
+    }
+
+    /// Stores a byte read from the input.
+    pub fn set(&mut self, i: usize, byte: u8) {
+        match &mut self.cells {
+            Cells::Bounded { cells, .. } => cells[i] = byte as u32,
+            Cells::Unbounded(cells) => cells[i] = BigCell::from(byte as i64),
+        }
+    }
+
+    /// Stores -1, which for bounded cells is their largest value.
+    pub fn set_max(&mut self, i: usize) {
+        match &mut self.cells {
+            Cells::Bounded { cells, mask } => cells[i] = *mask,
+            Cells::Unbounded(cells) => cells[i] = BigCell::from(-1),
+        }
+    }
+
+    /// The byte `.` prints for a cell: its value modulo 256.
+    #[inline]
+    pub fn byte(&self, i: usize) -> u8 {
+        match &self.cells {
+            Cells::Bounded { cells, .. } => cells[i] as u8,
+            Cells::Unbounded(cells) => cells[i].low_byte(),
+        }
+    }
+
+    /// Something that prints the value of a cell.
+    pub fn value(&self, i: usize) -> impl fmt::Display + '_ {
+        CellValue(self, i)
+    }
+}
+
+struct CellValue<'a>(&'a Tape, usize);
+
+impl fmt::Display for CellValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0.cells {
+            Cells::Bounded { cells, .. } => write!(f, "{}", cells[self.1]),
+            Cells::Unbounded(cells) => write!(f, "{}", cells[self.1]),
+        }
+    }
+}
+
+/// A signed integer of any size, with just the arithmetic brainfuck needs.
+///
+/// The magnitude is kept as little-endian base 2^32 digits without trailing
+/// zeros, so zero is an empty, non-negative number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BigCell {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+impl From<i64> for BigCell {
+    fn from(value: i64) -> Self {
+        let mut cell = BigCell::default();
+        cell.add(value);
+        cell
+    }
+}
+
+impl BigCell {
+    fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    fn add(&mut self, delta: i64) {
+        let amount = delta.unsigned_abs();
+        if amount == 0 {
+            return;
+        }
+
+        if self.is_zero() || self.negative == (delta < 0) {
+            self.add_magnitude(amount);
+            self.negative = delta < 0;
+        } else if let Some(small) = self.small().filter(|small| *small < amount) {
+            // The sign flips: |self| < amount, so the result is amount - |self|.
+            self.digits.clear();
+            self.add_magnitude(amount - small);
+            self.negative = delta < 0;
+        } else {
+            self.sub_magnitude(amount);
+            if self.is_zero() {
+                self.negative = false;
+            }
+        }
+    }
+
+    // The magnitude, if it fits in a u64.
+    fn small(&self) -> Option<u64> {
+        match self.digits[..] {
+            [] => Some(0),
+            [lo] => Some(lo as u64),
+            [lo, hi] => Some((hi as u64) << 32 | lo as u64),
+            _ => None,
+        }
+    }
+
+    fn add_magnitude(&mut self, amount: u64) {
+        let mut carry = amount;
+        for digit in self.digits.iter_mut() {
+            if carry == 0 {
+                break;
+            }
+            let sum = *digit as u64 + (carry & 0xffff_ffff);
+            *digit = sum as u32;
+            carry = (carry >> 32) + (sum >> 32);
+        }
+        while carry != 0 {
+            self.digits.push(carry as u32);
+            carry >>= 32;
+        }
+    }
+
+    // Requires |self| >= amount.
+    fn sub_magnitude(&mut self, amount: u64) {
+        let mut borrow = amount;
+        for digit in self.digits.iter_mut() {
+            if borrow == 0 {
+                break;
+            }
+            let take = borrow & 0xffff_ffff;
+            let (diff, under) = digit.overflowing_sub(take as u32);
+            *digit = diff;
+            borrow = (borrow >> 32) + under as u64;
+        }
+        while self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+    }
+
+    fn low_byte(&self) -> u8 {
+        let low = self.digits.first().copied().unwrap_or(0) as u8;
+        if self.negative {
+            low.wrapping_neg()
+        } else {
+            low
+        }
+    }
+}
+
+impl fmt::Display for BigCell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Peel off base 10^9 chunks, least significant first.
+        let mut digits = self.digits.clone();
+        let mut chunks = vec![];
+        while !digits.is_empty() {
+            let mut rem = 0u64;
+            for digit in digits.iter_mut().rev() {
+                let cur = rem << 32 | *digit as u64;
+                *digit = (cur / 1_000_000_000) as u32;
+                rem = cur % 1_000_000_000;
+            }
+            while digits.last() == Some(&0) {
+                digits.pop();
+            }
+            chunks.push(rem);
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1089 @@
+//! Lowers the IR to a WebAssembly module for WASI.
+//!
+//! The tape lives in linear memory and `,`/`.` go through WASI's `fd_read`
+//! and `fd_write`, so the module runs under any WASI runtime, such as
+//! `wasmtime program.wasm`. It can be printed as WAT or encoded as a binary
+//! module.
+
+use std::collections::HashMap;
+
+use crate::{
+    config::{BoundsPolicy, CellWidth, Config, EofPolicy, OverflowPolicy},
+    error::CodegenError,
+    ir::{Instruction, Loops, Representation},
+    lex::Span,
+};
+
+// Fixed addresses in linear memory: an iovec pointing at the byte being read
+// or written, one for trap messages, where WASI stores how much it read or
+// wrote, and the byte itself. Trap messages come after those.
+const IOV: i32 = 0;
+const MSG_IOV: i32 = 8;
+const DONE: i32 = 16;
+const BYTE: i32 = 20;
+const DATA: u32 = 24;
+
+const PAGE: u32 = 0x1_0000;
+// The most memory the tape may take, which keeps index arithmetic in i32.
+const MAX_TAPE_BYTES: u32 = 0x4000_0000;
+
+/// Generates a WebAssembly module for WASI from the IR.
+pub struct WasmGenerator {
+    loops: Loops,
+    data: Vec<u8>, // trap messages, from `DATA` on
+    config: Config,
+}
+
+impl Default for WasmGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WasmGenerator {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            loops: Loops::new(),
+            data: Vec::new(),
+            config,
+        }
+    }
+
+    /// The module in the WebAssembly text format.
+    pub fn generate(&mut self, rep: &Representation) -> Result<String, CodegenError> {
+        Ok(self.lower(rep)?.wat())
+    }
+
+    /// The module in the binary format.
+    pub fn binary(&mut self, rep: &Representation) -> Result<Vec<u8>, CodegenError> {
+        Ok(self.lower(rep)?.encode())
+    }
+
+    fn lower(&mut self, rep: &Representation) -> Result<Module, CodegenError> {
+        let bytes = match self.config.cell {
+            CellWidth::U8 => 1,
+            CellWidth::U16 => 2,
+            CellWidth::U32 => 4,
+            CellWidth::Unbounded => {
+                return Err(CodegenError::Unsupported(
+                    "Unbounded cells are not supported by the WebAssembly backend".to_string(),
+                ))
+            }
+        };
+        let len = self.config.tape_len.max(1);
+        if len as u64 * bytes as u64 > MAX_TAPE_BYTES as u64 {
+            return Err(CodegenError::Unsupported(
+                "The tape doesn't fit in WebAssembly memory".to_string(),
+            ));
+        }
+        let len = len as i32;
+
+        let mut body = Vec::new();
+        for (ins, span) in rep.instructions.iter().zip(&rep.spans) {
+            match ins {
+                Instruction::Add { offset, delta } => {
+                    self.index(&mut body, *offset, *span);
+                    body.push(Ins::LocalTee("a"));
+                    body.push(Ins::LocalGet("a"));
+                    body.push(Ins::Load(bytes, Mem::Tape));
+                    if self.config.overflow == OverflowPolicy::Trap {
+                        self.checked_add(&mut body, *delta, *span);
+                    } else {
+                        body.push(Ins::I32(*delta));
+                        body.push(Ins::Num(Num::I32Add));
+                    }
+                    body.push(Ins::Store(bytes, Mem::Tape));
+                }
+                Instruction::Move(n) => {
+                    self.seek(&mut body, *n, *span);
+                    body.push(Ins::GlobalSet("p"));
+                }
+                Instruction::Print { offset } => {
+                    self.index(&mut body, *offset, *span);
+                    body.push(Ins::Load(bytes, Mem::Tape));
+                    body.push(Ins::Call("putc"));
+                }
+                Instruction::Read { offset } => {
+                    self.index(&mut body, *offset, *span);
+                    match self.config.eof {
+                        // -1 is stored as the largest value
+                        EofPolicy::Max => body.push(Ins::Call("getc")),
+                        EofPolicy::Zero => body.extend([
+                            Ins::Call("getc"),
+                            Ins::LocalTee("c"),
+                            Ins::I32(0),
+                            Ins::LocalGet("c"),
+                            Ins::I32(-1),
+                            Ins::Num(Num::I32Ne),
+                            Ins::Select,
+                        ]),
+                        EofPolicy::Unchanged => {
+                            body.extend([
+                                Ins::LocalSet("a"),
+                                Ins::Call("getc"),
+                                Ins::LocalTee("c"),
+                                Ins::I32(-1),
+                                Ins::Num(Num::I32Ne),
+                                Ins::If,
+                                Ins::LocalGet("a"),
+                                Ins::LocalGet("c"),
+                                Ins::Store(bytes, Mem::Tape),
+                                Ins::End,
+                            ]);
+                            continue;
+                        }
+                    }
+                    body.push(Ins::Store(bytes, Mem::Tape));
+                }
+                Instruction::LoopStart => {
+                    let loop_id = self.loops.start();
+                    body.push(Ins::Block(format!("loop_end_{}", loop_id)));
+                    body.push(Ins::Loop(format!("loop_start_{}", loop_id)));
+                    self.index(&mut body, 0, *span);
+                    body.push(Ins::Load(bytes, Mem::Tape));
+                    body.push(Ins::Num(Num::I32Eqz));
+                    body.push(Ins::BrIf(format!("loop_end_{}", loop_id)));
+                }
+                Instruction::LoopEnd => {
+                    let loop_id = self.loops.end();
+                    body.push(Ins::Br(format!("loop_start_{}", loop_id)));
+                    body.push(Ins::End);
+                    body.push(Ins::End);
+                }
+            }
+        }
+
+        let mut funcs = vec![Func {
+            name: "_start",
+            params: vec![],
+            result: None,
+            locals: vec![("a", Type::I32), ("c", Type::I32), ("v", Type::I64)],
+            body,
+            export: true,
+        }];
+        funcs.extend(self.routines(len, bytes));
+        let funcs = reachable(funcs);
+
+        let tape = (DATA + self.data.len() as u32).next_multiple_of(8);
+        let mut globals = vec![("p", 0)];
+        if self.config.bounds == BoundsPolicy::Grow {
+            globals.push(("len", len));
+        }
+        let mut header = Vec::new();
+        header.extend(BYTE.to_le_bytes());
+        header.extend(1i32.to_le_bytes());
+        Ok(Module {
+            pages: (tape + len as u32 * bytes).div_ceil(PAGE),
+            globals,
+            data: [(IOV as u32, header), (DATA, std::mem::take(&mut self.data))]
+                .into_iter()
+                .filter(|(_, bytes)| !bytes.is_empty())
+                .collect(),
+            tape,
+            funcs,
+        })
+    }
+
+    // Leaves the byte offset of the cell `offset` cells away from the current
+    // one on the stack, relative to the start of the tape.
+    fn index(&mut self, body: &mut Vec<Ins>, offset: isize, span: Span) {
+        if offset == 0 {
+            body.push(Ins::GlobalGet("p"));
+        } else {
+            self.seek(body, offset, span);
+        }
+        let bytes = self.config.cell.bytes().unwrap_or(1) as i32;
+        if bytes > 1 {
+            body.push(Ins::I32(bytes));
+            body.push(Ins::Num(Num::I32Mul));
+        }
+    }
+
+    // Leaves the index of the cell `offset` cells away from the current one
+    // on the stack, as the bounds policy has it.
+    fn seek(&mut self, body: &mut Vec<Ins>, offset: isize, span: Span) {
+        let len = self.config.tape_len.max(1) as isize;
+        let offset = match self.config.bounds {
+            BoundsPolicy::Wrap => offset.rem_euclid(len),
+            // Far enough to be off any tape that fits
+            _ => offset.clamp(-(MAX_TAPE_BYTES as isize), MAX_TAPE_BYTES as isize),
+        };
+        body.push(Ins::I32(offset as i32));
+        if self.config.bounds == BoundsPolicy::Trap {
+            body.extend(self.message(format!("Pointer out of bounds at {}", span)));
+        }
+        body.push(Ins::Call("seek"));
+    }
+
+    // Adds `delta` to the cell value on the stack, stopping the program if it
+    // goes below 0 or past its largest value; `span` is the code to blame.
+    fn checked_add(&mut self, body: &mut Vec<Ins>, delta: i32, span: Span) {
+        let max = match self.config.cell {
+            CellWidth::U8 => u8::MAX as i64,
+            CellWidth::U16 => u16::MAX as i64,
+            _ => u32::MAX as i64,
+        };
+        body.extend([
+            Ins::Num(Num::I64ExtendI32U),
+            Ins::I64(delta as i64),
+            Ins::Num(Num::I64Add),
+            Ins::LocalTee("v"),
+        ]);
+        let direction = if delta < 0 {
+            body.extend([Ins::I64(0), Ins::Num(Num::I64LtS)]);
+            "below 0"
+        } else {
+            body.extend([Ins::I64(max), Ins::Num(Num::I64GtS)]);
+            "past its largest value"
+        };
+        body.push(Ins::If);
+        body.extend(self.message(format!(
+            "Cell overflow at {}: cell went {}",
+            span, direction
+        )));
+        body.push(Ins::Call("trap"));
+        body.push(Ins::End);
+        body.push(Ins::LocalGet("v"));
+        body.push(Ins::Num(Num::I32WrapI64));
+    }
+
+    // Stores a trap message, returning the code that leaves its address and
+    // length on the stack.
+    fn message(&mut self, message: String) -> [Ins; 2] {
+        let text = format!("Runtime error: {}\n", message).into_bytes();
+        let addr = DATA + self.data.len() as u32;
+        let len = text.len();
+        self.data.extend(text);
+        [Ins::I32(addr as i32), Ins::I32(len as i32)]
+    }
+
+    // The functions `_start` may call; `reachable` drops the ones it doesn't.
+    fn routines(&mut self, len: i32, bytes: u32) -> Vec<Func> {
+        let wasi = |f: &'static str, fd| {
+            vec![
+                Ins::I32(fd),
+                Ins::I32(IOV),
+                Ins::I32(1),
+                Ins::I32(DONE),
+                Ins::Call(f),
+                Ins::Drop,
+            ]
+        };
+        let scale = |body: &mut Vec<Ins>| {
+            if bytes > 1 {
+                body.push(Ins::I32(bytes as i32));
+                body.push(Ins::Num(Num::I32Mul));
+            }
+        };
+
+        // Writes the byte `c` to stdout
+        let mut putc = vec![
+            Ins::I32(BYTE),
+            Ins::LocalGet("c"),
+            Ins::Store(1, Mem::Plain),
+        ];
+        putc.extend(wasi("fd_write", 1));
+
+        // Reads a byte from stdin, or -1 at the end of input
+        let mut getc = vec![Ins::I32(DONE), Ins::I32(0), Ins::Store(4, Mem::Plain)];
+        getc.extend(wasi("fd_read", 0));
+        getc.extend([
+            Ins::I32(BYTE),
+            Ins::Load(1, Mem::Plain),
+            Ins::I32(-1),
+            Ins::I32(DONE),
+            Ins::Load(4, Mem::Plain),
+            Ins::Select,
+        ]);
+
+        // Prints the message at `msg` to stderr and exits with status 1
+        let trap = vec![
+            Ins::I32(MSG_IOV),
+            Ins::LocalGet("msg"),
+            Ins::Store(4, Mem::Plain),
+            Ins::I32(MSG_IOV + 4),
+            Ins::LocalGet("len"),
+            Ins::Store(4, Mem::Plain),
+            Ins::I32(2),
+            Ins::I32(MSG_IOV),
+            Ins::I32(1),
+            Ins::I32(DONE),
+            Ins::Call("fd_write"),
+            Ins::Drop,
+            Ins::I32(1),
+            Ins::Call("proc_exit"),
+            Ins::Unreachable,
+        ];
+
+        let mut seek = vec![
+            Ins::GlobalGet("p"),
+            Ins::LocalGet("offset"),
+            Ins::Num(Num::I32Add),
+        ];
+        let mut seek_params = vec![("offset", Type::I32)];
+        let mut seek_locals = vec![("i", Type::I32)];
+        let mut funcs = Vec::new();
+        match self.config.bounds {
+            // Offsets are never negative, and the pointer is on the tape.
+            BoundsPolicy::Wrap => {
+                seek.extend([Ins::I32(len), Ins::Num(Num::I32RemU), Ins::LocalSet("i")])
+            }
+            BoundsPolicy::Trap => {
+                seek_params.extend([("msg", Type::I32), ("len", Type::I32)]);
+                seek.extend([
+                    Ins::LocalTee("i"),
+                    Ins::I32(len),
+                    Ins::Num(Num::I32GeU),
+                    Ins::If,
+                    Ins::LocalGet("msg"),
+                    Ins::LocalGet("len"),
+                    Ins::Call("trap"),
+                    Ins::End,
+                ]);
+            }
+            BoundsPolicy::Grow => {
+                seek_locals.push(("n", Type::I32));
+                // n = max(n, len)
+                let at_least_len = [
+                    Ins::LocalTee("n"),
+                    Ins::GlobalGet("len"),
+                    Ins::LocalGet("n"),
+                    Ins::GlobalGet("len"),
+                    Ins::Num(Num::I32GtU),
+                    Ins::Select,
+                    Ins::LocalTee("n"),
+                    Ins::Call("reserve"),
+                ];
+                // Before the tape: move it up by n = max(-i, len) cells and
+                // clear the ones in front.
+                seek.extend([
+                    Ins::LocalTee("i"),
+                    Ins::I32(0),
+                    Ins::Num(Num::I32LtS),
+                    Ins::If,
+                    Ins::I32(0),
+                    Ins::LocalGet("i"),
+                    Ins::Num(Num::I32Sub),
+                ]);
+                seek.extend(at_least_len.clone());
+                seek.push(Ins::LocalGet("n"));
+                scale(&mut seek);
+                seek.extend([Ins::Tape(0), Ins::Num(Num::I32Add), Ins::Tape(0)]);
+                seek.push(Ins::GlobalGet("len"));
+                seek.push(Ins::LocalGet("n"));
+                seek.push(Ins::Num(Num::I32Sub));
+                scale(&mut seek);
+                seek.push(Ins::MemoryCopy);
+                seek.extend([Ins::Tape(0), Ins::I32(0), Ins::LocalGet("n")]);
+                scale(&mut seek);
+                seek.extend([
+                    Ins::MemoryFill,
+                    Ins::GlobalGet("p"),
+                    Ins::LocalGet("n"),
+                    Ins::Num(Num::I32Add),
+                    Ins::GlobalSet("p"),
+                    Ins::LocalGet("i"),
+                    Ins::LocalGet("n"),
+                    Ins::Num(Num::I32Add),
+                    Ins::LocalSet("i"),
+                    Ins::End,
+                ]);
+                // Past the end: add n = max(i - len + 1, len) cells.
+                seek.extend([
+                    Ins::LocalGet("i"),
+                    Ins::GlobalGet("len"),
+                    Ins::Num(Num::I32GeS),
+                    Ins::If,
+                    Ins::LocalGet("i"),
+                    Ins::GlobalGet("len"),
+                    Ins::Num(Num::I32Sub),
+                    Ins::I32(1),
+                    Ins::Num(Num::I32Add),
+                ]);
+                seek.extend(at_least_len);
+                seek.push(Ins::End);
+
+                // Makes the tape `n` cells longer, growing the memory if it
+                // has to. The new memory is zeroed.
+                let out_of_memory = self.message("Out of memory".to_string());
+                let mut reserve = vec![
+                    Ins::GlobalGet("len"),
+                    Ins::LocalGet("n"),
+                    Ins::Num(Num::I32Add),
+                    Ins::GlobalSet("len"),
+                    Ins::GlobalGet("len"),
+                    Ins::I32((MAX_TAPE_BYTES / bytes) as i32),
+                    Ins::Num(Num::I32GtU),
+                    Ins::If,
+                ];
+                reserve.extend(out_of_memory.clone());
+                reserve.extend([Ins::Call("trap"), Ins::End, Ins::GlobalGet("len")]);
+                scale(&mut reserve);
+                reserve.extend([
+                    Ins::Tape(PAGE - 1), // rounding up to whole pages
+                    Ins::Num(Num::I32Add),
+                    Ins::I32(16),
+                    Ins::Num(Num::I32ShrU),
+                    Ins::MemorySize,
+                    Ins::Num(Num::I32Sub),
+                    Ins::LocalTee("pages"),
+                    Ins::I32(0),
+                    Ins::Num(Num::I32GtS),
+                    Ins::If,
+                    Ins::LocalGet("pages"),
+                    Ins::MemoryGrow,
+                    Ins::I32(-1),
+                    Ins::Num(Num::I32Eq),
+                    Ins::If,
+                ]);
+                reserve.extend(out_of_memory);
+                reserve.extend([Ins::Call("trap"), Ins::End, Ins::End]);
+                funcs.push(Func {
+                    name: "reserve",
+                    params: vec![("n", Type::I32)],
+                    result: None,
+                    locals: vec![("pages", Type::I32)],
+                    body: reserve,
+                    export: false,
+                });
+            }
+        }
+        seek.push(Ins::LocalGet("i"));
+
+        funcs.extend([
+            Func {
+                name: "seek",
+                params: seek_params,
+                result: Some(Type::I32),
+                locals: seek_locals,
+                body: seek,
+                export: false,
+            },
+            Func {
+                name: "putc",
+                params: vec![("c", Type::I32)],
+                result: None,
+                locals: vec![],
+                body: putc,
+                export: false,
+            },
+            Func {
+                name: "getc",
+                params: vec![],
+                result: Some(Type::I32),
+                locals: vec![],
+                body: getc,
+                export: false,
+            },
+            Func {
+                name: "trap",
+                params: vec![("msg", Type::I32), ("len", Type::I32)],
+                result: None,
+                locals: vec![],
+                body: trap,
+                export: false,
+            },
+        ]);
+        funcs
+    }
+}
+
+// `funcs` without the ones the first never ends up calling, in order.
+fn reachable(funcs: Vec<Func>) -> Vec<Func> {
+    let mut called = vec![funcs[0].name];
+    let mut i = 0;
+    while i < called.len() {
+        let func = funcs.iter().find(|f| f.name == called[i]);
+        for ins in func.iter().flat_map(|f| &f.body) {
+            if let Ins::Call(name) = ins {
+                if !called.contains(name) {
+                    called.push(name);
+                }
+            }
+        }
+        i += 1;
+    }
+    funcs
+        .into_iter()
+        .filter(|f| called.contains(&f.name))
+        .collect()
+}
+
+// The WASI functions modules import, all from `wasi_snapshot_preview1`.
+const IMPORTS: [(&str, &[Type], Option<Type>); 3] = [
+    ("fd_write", &[Type::I32; 4], Some(Type::I32)),
+    ("fd_read", &[Type::I32; 4], Some(Type::I32)),
+    ("proc_exit", &[Type::I32], None),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    I32,
+    I64,
+}
+
+impl Type {
+    fn name(self) -> &'static str {
+        match self {
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Type::I32 => 0x7f,
+            Type::I64 => 0x7e,
+        }
+    }
+}
+
+// Which memory a load or store is relative to.
+#[derive(Debug, Clone, Copy)]
+enum Mem {
+    Tape,
+    Plain,
+}
+
+// Instructions that take no immediates.
+#[derive(Debug, Clone, Copy)]
+enum Num {
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32GtS,
+    I32GtU,
+    I32GeS,
+    I32GeU,
+    I64LtS,
+    I64GtS,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32RemU,
+    I32ShrU,
+    I64Add,
+    I32WrapI64,
+    I64ExtendI32U,
+}
+
+impl Num {
+    fn name(self) -> &'static str {
+        match self {
+            Num::I32Eqz => "i32.eqz",
+            Num::I32Eq => "i32.eq",
+            Num::I32Ne => "i32.ne",
+            Num::I32LtS => "i32.lt_s",
+            Num::I32GtS => "i32.gt_s",
+            Num::I32GtU => "i32.gt_u",
+            Num::I32GeS => "i32.ge_s",
+            Num::I32GeU => "i32.ge_u",
+            Num::I64LtS => "i64.lt_s",
+            Num::I64GtS => "i64.gt_s",
+            Num::I32Add => "i32.add",
+            Num::I32Sub => "i32.sub",
+            Num::I32Mul => "i32.mul",
+            Num::I32RemU => "i32.rem_u",
+            Num::I32ShrU => "i32.shr_u",
+            Num::I64Add => "i64.add",
+            Num::I32WrapI64 => "i32.wrap_i64",
+            Num::I64ExtendI32U => "i64.extend_i32_u",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Num::I32Eqz => 0x45,
+            Num::I32Eq => 0x46,
+            Num::I32Ne => 0x47,
+            Num::I32LtS => 0x48,
+            Num::I32GtS => 0x4a,
+            Num::I32GtU => 0x4b,
+            Num::I32GeS => 0x4e,
+            Num::I32GeU => 0x4f,
+            Num::I64LtS => 0x53,
+            Num::I64GtS => 0x55,
+            Num::I32Add => 0x6a,
+            Num::I32Sub => 0x6b,
+            Num::I32Mul => 0x6c,
+            Num::I32RemU => 0x70,
+            Num::I32ShrU => 0x76,
+            Num::I64Add => 0x7c,
+            Num::I32WrapI64 => 0xa7,
+            Num::I64ExtendI32U => 0xad,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Ins {
+    Block(String),
+    Loop(String),
+    If,
+    End,
+    Br(String),
+    BrIf(String),
+    Call(&'static str),
+    Drop,
+    Select,
+    Unreachable,
+    LocalGet(&'static str),
+    LocalSet(&'static str),
+    LocalTee(&'static str),
+    GlobalGet(&'static str),
+    GlobalSet(&'static str),
+    /// A load of 1, 2 or 4 bytes, zero-extended
+    Load(u32, Mem),
+    Store(u32, Mem),
+    I32(i32),
+    I64(i64),
+    /// An address this far into the tape
+    Tape(u32),
+    Num(Num),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+}
+
+struct Func {
+    name: &'static str,
+    params: Vec<(&'static str, Type)>,
+    result: Option<Type>,
+    locals: Vec<(&'static str, Type)>,
+    body: Vec<Ins>,
+    export: bool,
+}
+
+impl Func {
+    fn local(&self, name: &str) -> u32 {
+        let position = self
+            .params
+            .iter()
+            .chain(&self.locals)
+            .position(|(n, _)| *n == name);
+        position.expect("locals are declared") as u32
+    }
+}
+
+struct Module {
+    pages: u32,
+    globals: Vec<(&'static str, i32)>,
+    data: Vec<(u32, Vec<u8>)>,
+    tape: u32,
+    funcs: Vec<Func>,
+}
+
+impl Module {
+    fn wat(&self) -> String {
+        let mut out = vec!["(module".to_string()];
+        for (name, params, result) in IMPORTS {
+            out.push(format!(
+                "  (import \"wasi_snapshot_preview1\" \"{}\" (func ${}{}))",
+                name,
+                name,
+                signature(params.iter().map(|t| (None, *t)), result)
+            ));
+        }
+        out.push(format!("  (memory (export \"memory\") {})", self.pages));
+        for (name, value) in &self.globals {
+            out.push(format!(
+                "  (global ${} (mut i32) (i32.const {}))",
+                name, value
+            ));
+        }
+        for (addr, bytes) in &self.data {
+            out.push(format!(
+                "  (data (i32.const {}) \"{}\")",
+                addr,
+                escape(bytes)
+            ));
+        }
+
+        for func in &self.funcs {
+            out.push("".to_string());
+            let export = if func.export {
+                format!(" (export \"{}\")", func.name)
+            } else {
+                String::new()
+            };
+            out.push(format!(
+                "  (func ${}{}{}",
+                func.name,
+                export,
+                signature(func.params.iter().map(|(n, t)| (Some(*n), *t)), func.result)
+            ));
+            if !func.locals.is_empty() {
+                let locals: Vec<String> = func
+                    .locals
+                    .iter()
+                    .map(|(n, t)| format!("(local ${} {})", n, t.name()))
+                    .collect();
+                out.push(format!("    {}", locals.join(" ")));
+            }
+            let mut depth = 2;
+            for ins in &func.body {
+                if let Ins::End = ins {
+                    depth -= 1;
+                }
+                out.push(format!("{}{}", "  ".repeat(depth), self.ins_text(ins)));
+                if let Ins::Block(_) | Ins::Loop(_) | Ins::If = ins {
+                    depth += 1;
+                }
+            }
+            out.push("  )".to_string());
+        }
+        out.push(")".to_string());
+        out.push("".to_string());
+        out.join("\n")
+    }
+
+    fn ins_text(&self, ins: &Ins) -> String {
+        match ins {
+            Ins::Block(label) => format!("block ${}", label),
+            Ins::Loop(label) => format!("loop ${}", label),
+            Ins::If => "if".to_string(),
+            Ins::End => "end".to_string(),
+            Ins::Br(label) => format!("br ${}", label),
+            Ins::BrIf(label) => format!("br_if ${}", label),
+            Ins::Call(name) => format!("call ${}", name),
+            Ins::Drop => "drop".to_string(),
+            Ins::Select => "select".to_string(),
+            Ins::Unreachable => "unreachable".to_string(),
+            Ins::LocalGet(name) => format!("local.get ${}", name),
+            Ins::LocalSet(name) => format!("local.set ${}", name),
+            Ins::LocalTee(name) => format!("local.tee ${}", name),
+            Ins::GlobalGet(name) => format!("global.get ${}", name),
+            Ins::GlobalSet(name) => format!("global.set ${}", name),
+            Ins::Load(bytes, mem) => format!("{}{}", load_name(*bytes), self.offset_text(*mem)),
+            Ins::Store(bytes, mem) => format!("{}{}", store_name(*bytes), self.offset_text(*mem)),
+            Ins::I32(value) => format!("i32.const {}", value),
+            Ins::I64(value) => format!("i64.const {}", value),
+            Ins::Tape(offset) => format!("i32.const {}", self.tape + offset),
+            Ins::Num(num) => num.name().to_string(),
+            Ins::MemorySize => "memory.size".to_string(),
+            Ins::MemoryGrow => "memory.grow".to_string(),
+            Ins::MemoryCopy => "memory.copy".to_string(),
+            Ins::MemoryFill => "memory.fill".to_string(),
+        }
+    }
+
+    fn offset_text(&self, mem: Mem) -> String {
+        match mem {
+            Mem::Tape if self.tape > 0 => format!(" offset={}", self.tape),
+            _ => String::new(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = b"\0asm".to_vec();
+        out.extend(1u32.to_le_bytes());
+
+        // Every distinct signature, imports first
+        let mut types: Vec<(Vec<Type>, Option<Type>)> = Vec::new();
+        let mut type_of = |params: Vec<Type>, result: Option<Type>| {
+            let sig = (params, result);
+            match types.iter().position(|t| *t == sig) {
+                Some(i) => i as u32,
+                None => {
+                    types.push(sig);
+                    types.len() as u32 - 1
+                }
+            }
+        };
+        let import_types: Vec<u32> = IMPORTS
+            .iter()
+            .map(|(_, params, result)| type_of(params.to_vec(), *result))
+            .collect();
+        let func_types: Vec<u32> = self
+            .funcs
+            .iter()
+            .map(|f| type_of(f.params.iter().map(|(_, t)| *t).collect(), f.result))
+            .collect();
+
+        let mut section = Vec::new();
+        uleb(&mut section, types.len() as u64);
+        for (params, result) in &types {
+            section.push(0x60);
+            uleb(&mut section, params.len() as u64);
+            section.extend(params.iter().map(|t| t.code()));
+            uleb(&mut section, result.is_some() as u64);
+            section.extend(result.iter().map(|t| t.code()));
+        }
+        push_section(&mut out, 1, section);
+
+        let mut section = Vec::new();
+        uleb(&mut section, IMPORTS.len() as u64);
+        for ((name, _, _), ty) in IMPORTS.iter().zip(import_types) {
+            name_bytes(&mut section, "wasi_snapshot_preview1");
+            name_bytes(&mut section, name);
+            section.push(0x00); // a function
+            uleb(&mut section, ty as u64);
+        }
+        push_section(&mut out, 2, section);
+
+        let mut section = Vec::new();
+        uleb(&mut section, func_types.len() as u64);
+        for ty in func_types {
+            uleb(&mut section, ty as u64);
+        }
+        push_section(&mut out, 3, section);
+
+        let mut section = vec![1, 0x00]; // one memory, with no maximum
+        uleb(&mut section, self.pages as u64);
+        push_section(&mut out, 5, section);
+
+        let mut section = Vec::new();
+        uleb(&mut section, self.globals.len() as u64);
+        for (_, value) in &self.globals {
+            section.extend([Type::I32.code(), 0x01, 0x41]); // mutable, i32.const
+            sleb(&mut section, *value as i64);
+            section.push(0x0b);
+        }
+        push_section(&mut out, 6, section);
+
+        let funcs: HashMap<&str, u32> = IMPORTS
+            .iter()
+            .map(|(name, _, _)| *name)
+            .chain(self.funcs.iter().map(|f| f.name))
+            .enumerate()
+            .map(|(i, name)| (name, i as u32))
+            .collect();
+        let mut section = Vec::new();
+        uleb(&mut section, 2);
+        name_bytes(&mut section, "memory");
+        section.extend([0x02, 0x00]);
+        let start = self
+            .funcs
+            .iter()
+            .find(|f| f.export)
+            .expect("an entry point");
+        name_bytes(&mut section, start.name);
+        section.push(0x00);
+        uleb(&mut section, funcs[start.name] as u64);
+        push_section(&mut out, 7, section);
+
+        let mut section = Vec::new();
+        uleb(&mut section, self.funcs.len() as u64);
+        for func in &self.funcs {
+            let mut code = Vec::new();
+            uleb(&mut code, func.locals.len() as u64);
+            for (_, ty) in &func.locals {
+                code.extend([1, ty.code()]);
+            }
+            let mut labels: Vec<Option<&str>> = Vec::new();
+            for ins in &func.body {
+                self.encode_ins(&mut code, ins, func, &funcs, &mut labels);
+            }
+            code.push(0x0b);
+            uleb(&mut section, code.len() as u64);
+            section.extend(code);
+        }
+        push_section(&mut out, 10, section);
+
+        let mut section = Vec::new();
+        uleb(&mut section, self.data.len() as u64);
+        for (addr, bytes) in &self.data {
+            section.extend([0x00, 0x41]); // memory 0, at i32.const
+            sleb(&mut section, *addr as i64);
+            section.push(0x0b);
+            uleb(&mut section, bytes.len() as u64);
+            section.extend(bytes);
+        }
+        push_section(&mut out, 11, section);
+        out
+    }
+
+    fn encode_ins<'a>(
+        &self,
+        out: &mut Vec<u8>,
+        ins: &'a Ins,
+        func: &Func,
+        funcs: &HashMap<&str, u32>,
+        labels: &mut Vec<Option<&'a str>>,
+    ) {
+        // Branches count the blocks they leave.
+        let depth = |labels: &Vec<Option<&str>>, label: &str| {
+            let position = labels.iter().rev().position(|l| *l == Some(label));
+            position.expect("branches go to enclosing blocks") as u64
+        };
+        match ins {
+            Ins::Block(label) => {
+                out.extend([0x02, 0x40]);
+                labels.push(Some(label.as_str()));
+            }
+            Ins::Loop(label) => {
+                out.extend([0x03, 0x40]);
+                labels.push(Some(label.as_str()));
+            }
+            Ins::If => {
+                out.extend([0x04, 0x40]);
+                labels.push(None);
+            }
+            Ins::End => {
+                out.push(0x0b);
+                labels.pop();
+            }
+            Ins::Br(label) => {
+                out.push(0x0c);
+                uleb(out, depth(labels, label));
+            }
+            Ins::BrIf(label) => {
+                out.push(0x0d);
+                uleb(out, depth(labels, label));
+            }
+            Ins::Call(name) => {
+                out.push(0x10);
+                uleb(out, funcs[name] as u64);
+            }
+            Ins::Drop => out.push(0x1a),
+            Ins::Select => out.push(0x1b),
+            Ins::Unreachable => out.push(0x00),
+            Ins::LocalGet(name) => {
+                out.push(0x20);
+                uleb(out, func.local(name) as u64);
+            }
+            Ins::LocalSet(name) => {
+                out.push(0x21);
+                uleb(out, func.local(name) as u64);
+            }
+            Ins::LocalTee(name) => {
+                out.push(0x22);
+                uleb(out, func.local(name) as u64);
+            }
+            Ins::GlobalGet(name) | Ins::GlobalSet(name) => {
+                out.push(if let Ins::GlobalGet(_) = ins {
+                    0x23
+                } else {
+                    0x24
+                });
+                let index = self.globals.iter().position(|(n, _)| n == name);
+                uleb(out, index.expect("globals are declared") as u64);
+            }
+            Ins::Load(bytes, mem) | Ins::Store(bytes, mem) => {
+                let load = matches!(ins, Ins::Load(..));
+                out.push(match (load, bytes) {
+                    (true, 1) => 0x2d,
+                    (true, 2) => 0x2f,
+                    (true, _) => 0x28,
+                    (false, 1) => 0x3a,
+                    (false, 2) => 0x3b,
+                    (false, _) => 0x36,
+                });
+                uleb(out, bytes.trailing_zeros() as u64); // natural alignment
+                let offset = match mem {
+                    Mem::Tape => self.tape,
+                    Mem::Plain => 0,
+                };
+                uleb(out, offset as u64);
+            }
+            Ins::I32(value) => {
+                out.push(0x41);
+                sleb(out, *value as i64);
+            }
+            Ins::I64(value) => {
+                out.push(0x42);
+                sleb(out, *value);
+            }
+            Ins::Tape(offset) => {
+                out.push(0x41);
+                sleb(out, (self.tape + offset) as i32 as i64);
+            }
+            Ins::Num(num) => out.push(num.code()),
+            Ins::MemorySize => out.extend([0x3f, 0x00]),
+            Ins::MemoryGrow => out.extend([0x40, 0x00]),
+            Ins::MemoryCopy => out.extend([0xfc, 10, 0x00, 0x00]),
+            Ins::MemoryFill => out.extend([0xfc, 11, 0x00]),
+        }
+    }
+}
+
+// ` (param ...) (result ...)`, with names where there are any.
+fn signature(
+    params: impl Iterator<Item = (Option<&'static str>, Type)>,
+    result: Option<Type>,
+) -> String {
+    let mut out = String::new();
+    let mut unnamed = Vec::new();
+    for (name, ty) in params {
+        match name {
+            Some(name) => out.push_str(&format!(" (param ${} {})", name, ty.name())),
+            None => unnamed.push(ty.name()),
+        }
+    }
+    if !unnamed.is_empty() {
+        out.push_str(&format!(" (param {})", unnamed.join(" ")));
+    }
+    if let Some(ty) = result {
+        out.push_str(&format!(" (result {})", ty.name()));
+    }
+    out
+}
+
+fn load_name(bytes: u32) -> &'static str {
+    match bytes {
+        1 => "i32.load8_u",
+        2 => "i32.load16_u",
+        _ => "i32.load",
+    }
+}
+
+fn store_name(bytes: u32) -> &'static str {
+    match bytes {
+        1 => "i32.store8",
+        2 => "i32.store16",
+        _ => "i32.store",
+    }
+}
+
+// `bytes` as the inside of a WAT string.
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b' '..=b'~' if b != b'"' && b != b'\\' => (b as char).to_string(),
+            _ => format!("\\{:02x}", b),
+        })
+        .collect()
+}
+
+fn push_section(out: &mut Vec<u8>, id: u8, section: Vec<u8>) {
+    out.push(id);
+    uleb(out, section.len() as u64);
+    out.extend(section);
+}
+
+fn name_bytes(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,531 @@
+//! The x86 instructions the native backend needs, and an encoder turning
+//! them into machine code.
+//!
+//! [`FasmGenerator`](crate::FasmGenerator) lowers the IR to an [`Assembly`],
+//! which is either printed as source for an assembler or encoded here and
+//! wrapped up by [`elf`](crate::elf). Only the forms the generator uses are
+//! supported, and every jump and label reference takes 32 bits, so the size
+//! of each instruction is known before any label has an address.
+
+use std::collections::HashMap;
+
+use crate::{config::Target, error::CodegenError};
+
+/// A general purpose register, named after its 64-bit self.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    /// The number the encoding uses for it.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Its name when used at `size`.
+    pub fn name(self, size: Size) -> &'static str {
+        const NAMES: [[&str; 16]; 4] = [
+            [
+                "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b",
+                "r12b", "r13b", "r14b", "r15b",
+            ],
+            [
+                "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w",
+                "r12w", "r13w", "r14w", "r15w",
+            ],
+            [
+                "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d",
+                "r11d", "r12d", "r13d", "r14d", "r15d",
+            ],
+            [
+                "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11",
+                "r12", "r13", "r14", "r15",
+            ],
+        ];
+        NAMES[size as usize][self as usize]
+    }
+}
+
+/// How wide an operand is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl Size {
+    pub fn bytes(self) -> usize {
+        1 << self as usize
+    }
+
+    /// The size of addresses and of the registers holding them on `target`.
+    pub fn of_addresses(target: Target) -> Self {
+        match target {
+            Target::X86 => Size::Dword,
+            Target::X86_64 => Size::Qword,
+        }
+    }
+}
+
+/// A memory operand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mem {
+    /// `[reg + disp]`
+    Base(Reg, i32),
+    /// The data or code at a label: an absolute address on x86, relative to
+    /// the next instruction on x86-64.
+    Label(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+    Mem(Mem),
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<i64> for Operand {
+    fn from(imm: i64) -> Self {
+        Operand::Imm(imm)
+    }
+}
+
+impl From<Mem> for Operand {
+    fn from(mem: Mem) -> Self {
+        Operand::Mem(mem)
+    }
+}
+
+/// The arithmetic instructions sharing one encoding scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alu {
+    Add,
+    Sub,
+    Cmp,
+}
+
+impl Alu {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Alu::Add => "add",
+            Alu::Sub => "sub",
+            Alu::Cmp => "cmp",
+        }
+    }
+
+    // The `/digit` of the immediate forms.
+    fn ext(self) -> u8 {
+        match self {
+            Alu::Add => 0,
+            Alu::Sub => 5,
+            Alu::Cmp => 7,
+        }
+    }
+}
+
+/// Conditions for `j<cc>`, all of them unsigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    /// Below, i.e. carry set
+    B,
+    /// Carry set, the same as `B` but reads better after an add
+    C,
+    Ae,
+    E,
+    Ne,
+    Be,
+}
+
+impl Cond {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Cond::B => "jb",
+            Cond::C => "jc",
+            Cond::Ae => "jae",
+            Cond::E => "je",
+            Cond::Ne => "jne",
+            Cond::Be => "jbe",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Cond::B | Cond::C => 0x2,
+            Cond::Ae => 0x3,
+            Cond::E => 0x4,
+            Cond::Ne => 0x5,
+            Cond::Be => 0x6,
+        }
+    }
+}
+
+/// One instruction. Registers in memory operands and the destination of
+/// `Lea` are address sized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Mov(Size, Operand, Operand),
+    /// Zero-extends a byte into the 32-bit part of the register
+    Movzx(Reg, Mem),
+    Lea(Reg, Mem),
+    Alu(Alu, Size, Operand, Operand),
+    Jmp(String),
+    J(Cond, String),
+    Call(String),
+    /// Calls the address stored at a memory operand
+    CallPtr(Mem),
+    Push(Reg),
+    Pop(Reg),
+    Ret,
+    Int(u8),
+    Syscall,
+}
+
+/// A line of an [`Assembly`]'s code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Label(String),
+    /// An instruction, with a comment to print after it
+    Op(Op, Option<String>),
+    /// A comment on a line of its own
+    Comment(String),
+    Blank,
+}
+
+/// Where execution starts.
+pub const ENTRY: &str = "start";
+
+/// A whole program for x86 Linux, ready to be printed or encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub target: Target,
+    /// Zeroed memory, as a label, the size of an element and how many
+    pub bss: Vec<(String, Size, usize)>,
+    /// The code, starting at [`ENTRY`]
+    pub text: Vec<Line>,
+    /// Read-only bytes with a label each
+    pub rodata: Vec<(String, Vec<u8>)>,
+}
+
+/// Encoded instructions, along with the label references to fill in once
+/// everything has an address.
+#[derive(Debug, Default)]
+pub struct Code {
+    pub bytes: Vec<u8>,
+    /// Offsets of the labels defined in the code
+    pub labels: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+}
+
+// A 32-bit field at `at` to be filled in with the address of `label`, or its
+// distance from `end` if relative.
+#[derive(Debug)]
+struct Fixup {
+    at: usize,
+    label: String,
+    relative: Option<usize>,
+}
+
+impl Code {
+    /// Fills in every label reference, with the code loaded at `base` and
+    /// labels outside of it at the addresses in `symbols`.
+    pub fn link(&mut self, base: u64, symbols: &HashMap<String, u64>) -> Result<(), CodegenError> {
+        for fixup in &self.fixups {
+            let target = match self.labels.get(&fixup.label) {
+                Some(&offset) => base + offset as u64,
+                None => *symbols.get(&fixup.label).ok_or_else(|| {
+                    CodegenError::Assembler(format!("undefined label `{}`", fixup.label))
+                })?,
+            };
+            let value = match fixup.relative {
+                Some(end) => {
+                    i32::try_from(target as i64 - (base + end as u64) as i64).map_err(|_| {
+                        CodegenError::Assembler(format!("`{}` is too far away", fixup.label))
+                    })? as u32
+                }
+                None => u32::try_from(target).map_err(|_| {
+                    CodegenError::Assembler(format!("`{}` is out of reach", fixup.label))
+                })?,
+            };
+            self.bytes[fixup.at..fixup.at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `lines` for `target`. Label references are left as zeroes until
+/// [`Code::link`].
+pub fn encode(lines: &[Line], target: Target) -> Result<Code, CodegenError> {
+    let mut encoder = Encoder {
+        code: Code::default(),
+        x86_64: target == Target::X86_64,
+        addr: Size::of_addresses(target),
+    };
+    for line in lines {
+        match line {
+            Line::Label(name) => {
+                let offset = encoder.code.bytes.len();
+                if encoder.code.labels.insert(name.clone(), offset).is_some() {
+                    return Err(CodegenError::Assembler(format!(
+                        "label `{}` is defined twice",
+                        name
+                    )));
+                }
+            }
+            Line::Op(op, _) => encoder.op(op)?,
+            Line::Comment(_) | Line::Blank => {}
+        }
+    }
+    Ok(encoder.code)
+}
+
+// The register or memory operand of a ModRM byte.
+enum Rm<'a> {
+    Reg(Reg),
+    Mem(&'a Mem),
+}
+
+struct Encoder {
+    code: Code,
+    x86_64: bool,
+    addr: Size,
+}
+
+impl Encoder {
+    fn op(&mut self, op: &Op) -> Result<(), CodegenError> {
+        match op {
+            Op::Mov(size, Operand::Reg(dst), Operand::Imm(imm)) => {
+                if *size == Size::Qword {
+                    self.modrm(*size, &[0xc7], 0, Rm::Reg(*dst), &imm32(*imm)?)
+                } else {
+                    // B8+r, with the register in the opcode
+                    let opcode = if *size == Size::Byte { 0xb0 } else { 0xb8 };
+                    self.prefixes(*size, 0, dst.code())?;
+                    self.code.bytes.push(opcode + (dst.code() & 7));
+                    self.code.bytes.extend(immediate(*imm, *size));
+                    Ok(())
+                }
+            }
+            Op::Mov(size, dst, Operand::Imm(imm)) => {
+                let opcode = if *size == Size::Byte { 0xc6 } else { 0xc7 };
+                let rm = self.rm(dst)?;
+                let imm = match size {
+                    Size::Qword => imm32(*imm)?,
+                    _ => immediate(*imm, *size),
+                };
+                self.modrm(*size, &[opcode], 0, rm, &imm)
+            }
+            Op::Mov(size, dst, Operand::Reg(src)) => {
+                let opcode = if *size == Size::Byte { 0x88 } else { 0x89 };
+                let rm = self.rm(dst)?;
+                self.modrm(*size, &[opcode], src.code(), rm, &[])
+            }
+            Op::Mov(size, Operand::Reg(dst), Operand::Mem(src)) => {
+                let opcode = if *size == Size::Byte { 0x8a } else { 0x8b };
+                self.modrm(*size, &[opcode], dst.code(), Rm::Mem(src), &[])
+            }
+            Op::Movzx(dst, src) => {
+                self.modrm(Size::Dword, &[0x0f, 0xb6], dst.code(), Rm::Mem(src), &[])
+            }
+            Op::Lea(dst, src) => self.modrm(self.addr, &[0x8d], dst.code(), Rm::Mem(src), &[]),
+            Op::Alu(alu, size, dst, Operand::Imm(imm)) => {
+                let rm = self.rm(dst)?;
+                let short = (-128..=127).contains(&sign_extend(*imm, *size));
+                let (opcode, imm) = match size {
+                    Size::Byte => (0x80, immediate(*imm, *size)),
+                    _ if short => (0x83, immediate(*imm, Size::Byte)),
+                    Size::Qword => (0x81, imm32(*imm)?),
+                    _ => (0x81, immediate(*imm, *size)),
+                };
+                self.modrm(*size, &[opcode], alu.ext(), rm, &imm)
+            }
+            Op::Alu(alu, size, dst, Operand::Reg(src)) => {
+                // add r/m, r is 01 (00 for bytes), and so on in steps of 8
+                let opcode = alu.ext() * 8 + if *size == Size::Byte { 0 } else { 1 };
+                let rm = self.rm(dst)?;
+                self.modrm(*size, &[opcode], src.code(), rm, &[])
+            }
+            Op::Alu(alu, size, Operand::Reg(dst), Operand::Mem(src)) => {
+                let opcode = alu.ext() * 8 + if *size == Size::Byte { 2 } else { 3 };
+                self.modrm(*size, &[opcode], dst.code(), Rm::Mem(src), &[])
+            }
+            Op::Jmp(label) => self.branch(&[0xe9], label),
+            Op::J(cond, label) => self.branch(&[0x0f, 0x80 + cond.code()], label),
+            Op::Call(label) => self.branch(&[0xe8], label),
+            // FF /2, which takes the address size without a REX.W
+            Op::CallPtr(mem) => self.modrm(Size::Dword, &[0xff], 2, Rm::Mem(mem), &[]),
+            Op::Push(reg) | Op::Pop(reg) => {
+                let opcode = if matches!(op, Op::Push(_)) {
+                    0x50
+                } else {
+                    0x58
+                };
+                self.prefixes(Size::Dword, 0, reg.code())?;
+                self.code.bytes.push(opcode + (reg.code() & 7));
+                Ok(())
+            }
+            Op::Ret => {
+                self.code.bytes.push(0xc3);
+                Ok(())
+            }
+            Op::Int(n) => {
+                self.code.bytes.extend([0xcd, *n]);
+                Ok(())
+            }
+            Op::Syscall if self.x86_64 => {
+                self.code.bytes.extend([0x0f, 0x05]);
+                Ok(())
+            }
+            op => Err(CodegenError::Assembler(format!("can't encode {:?}", op))),
+        }
+    }
+
+    fn rm<'a>(&self, operand: &'a Operand) -> Result<Rm<'a>, CodegenError> {
+        match operand {
+            Operand::Reg(reg) => Ok(Rm::Reg(*reg)),
+            Operand::Mem(mem) => Ok(Rm::Mem(mem)),
+            Operand::Imm(_) => Err(CodegenError::Assembler(
+                "an immediate can't be a destination".to_string(),
+            )),
+        }
+    }
+
+    // The operand size prefix and REX byte, if needed, for an instruction
+    // with `reg` in the reg field and `base` as its register or memory base.
+    fn prefixes(&mut self, size: Size, reg: u8, base: u8) -> Result<(), CodegenError> {
+        if size == Size::Word {
+            self.code.bytes.push(0x66);
+        }
+        let mut rex = 0;
+        if size == Size::Qword {
+            rex |= 0x08;
+        }
+        if reg >= 8 {
+            rex |= 0x04;
+        }
+        if base >= 8 {
+            rex |= 0x01;
+        }
+        if rex != 0 {
+            if !self.x86_64 {
+                return Err(CodegenError::Assembler(
+                    "64-bit operands don't exist on x86".to_string(),
+                ));
+            }
+            self.code.bytes.push(0x40 | rex);
+        }
+        Ok(())
+    }
+
+    // An instruction taking a ModRM byte, followed by `imm`.
+    fn modrm(
+        &mut self,
+        size: Size,
+        opcode: &[u8],
+        reg: u8,
+        rm: Rm,
+        imm: &[u8],
+    ) -> Result<(), CodegenError> {
+        let base = match rm {
+            Rm::Reg(r) | Rm::Mem(&Mem::Base(r, _)) => r.code(),
+            Rm::Mem(Mem::Label(_)) => 0,
+        };
+        self.prefixes(size, reg, base)?;
+        self.code.bytes.extend(opcode);
+
+        let reg = (reg & 7) << 3;
+        let mut fixup = None;
+        match rm {
+            Rm::Reg(r) => self.code.bytes.push(0xc0 | reg | (r.code() & 7)),
+            Rm::Mem(Mem::Base(r, disp)) => {
+                let base = r.code() & 7;
+                // rbp and r13 have no form without a displacement
+                let mode = match disp {
+                    0 if base != 5 => 0x00,
+                    -128..=127 => 0x40,
+                    _ => 0x80,
+                };
+                self.code.bytes.push(mode | reg | base);
+                if base == 4 {
+                    self.code.bytes.push(0x24); // SIB for rsp and r12
+                }
+                match mode {
+                    0x40 => self.code.bytes.push(*disp as u8),
+                    0x80 => self.code.bytes.extend(disp.to_le_bytes()),
+                    _ => {}
+                }
+            }
+            Rm::Mem(Mem::Label(label)) => {
+                // disp32 on its own, which x86-64 reads as RIP-relative
+                self.code.bytes.push(reg | 0x05);
+                fixup = Some((self.code.bytes.len(), label.clone()));
+                self.code.bytes.extend([0; 4]);
+            }
+        }
+        self.code.bytes.extend(imm);
+
+        if let Some((at, label)) = fixup {
+            let relative = self.x86_64.then_some(self.code.bytes.len());
+            self.code.fixups.push(Fixup {
+                at,
+                label,
+                relative,
+            });
+        }
+        Ok(())
+    }
+
+    // A jump or call to `label`, 32 bits away at most.
+    fn branch(&mut self, opcode: &[u8], label: &str) -> Result<(), CodegenError> {
+        self.code.bytes.extend(opcode);
+        let at = self.code.bytes.len();
+        self.code.bytes.extend([0; 4]);
+        self.code.fixups.push(Fixup {
+            at,
+            label: label.to_string(),
+            relative: Some(self.code.bytes.len()),
+        });
+        Ok(())
+    }
+}
+
+// `imm` as a `size` operand, keeping the low bits.
+fn immediate(imm: i64, size: Size) -> Vec<u8> {
+    imm.to_le_bytes()[..size.bytes()].to_vec()
+}
+
+// `imm` as the sign-extended 32 bits 64-bit instructions take.
+fn imm32(imm: i64) -> Result<Vec<u8>, CodegenError> {
+    let imm = i32::try_from(imm)
+        .map_err(|_| CodegenError::Assembler(format!("{} doesn't fit in 32 bits", imm)))?;
+    Ok(imm.to_le_bytes().to_vec())
+}
+
+// What the low `size` bits of `imm` are worth as a signed number.
+fn sign_extend(imm: i64, size: Size) -> i64 {
+    let unused = 64 - 8 * size.bytes() as u32;
+    (imm << unused) >> unused
+}
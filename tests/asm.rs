@@ -0,0 +1,65 @@
+use brainfuck::{Config, Dialect, Program, Target};
+
+fn render(src: &str, target: Target, dialect: Dialect) -> String {
+    Program::parse(src)
+        .unwrap()
+        .with_config(Config {
+            target,
+            ..Default::default()
+        })
+        .assembly_in(dialect)
+        .unwrap()
+}
+
+#[test]
+fn fasm_is_the_default() {
+    let program = Program::parse("+>,.").unwrap();
+    assert_eq!(
+        program.assembly().unwrap(),
+        program.assembly_in(Dialect::Fasm).unwrap()
+    );
+}
+
+#[test]
+fn dialects_spell_instructions_their_own_way() {
+    for (dialect, target, expected) in [
+        (Dialect::Fasm, Target::X86, "add byte [ebp], 1"),
+        (Dialect::Nasm, Target::X86_64, "add byte [rbp], 1"),
+        (Dialect::GasIntel, Target::X86_64, "add byte ptr [rbp], 1"),
+        (Dialect::GasAtt, Target::X86, "addb $1, (%ebp)"),
+        (Dialect::GasAtt, Target::X86_64, "addb $1, (%rbp)"),
+    ] {
+        let asm = render("+", target, dialect);
+        assert!(asm.contains(expected), "{:?}:\n{}", dialect, asm);
+    }
+}
+
+#[test]
+fn tape_addresses_are_rip_relative_on_x86_64() {
+    let asm = render("+", Target::X86_64, Dialect::Nasm);
+    assert!(asm.contains("default rel"));
+    assert!(asm.contains("lea rbp, [tape]"));
+    let asm = render("+", Target::X86_64, Dialect::GasIntel);
+    assert!(asm.contains("lea rbp, [rip + tape]"));
+    let asm = render("+", Target::X86_64, Dialect::GasAtt);
+    assert!(asm.contains("leaq tape(%rip), %rbp"));
+    let asm = render("+", Target::X86, Dialect::GasAtt);
+    assert!(asm.contains("leal tape, %ebp"));
+}
+
+#[test]
+fn linked_dialects_export_the_entry_point() {
+    assert!(render("+", Target::X86, Dialect::Nasm).contains("global start"));
+    assert!(render("+", Target::X86, Dialect::GasIntel).contains(".globl start"));
+    assert!(render("+", Target::X86, Dialect::GasAtt).contains(".globl start"));
+}
+
+#[test]
+fn wrapping_only_calls_out_when_the_address_left_the_tape() {
+    let asm = render("+>+<<-", Target::X86, Dialect::Fasm);
+    assert!(asm.contains("    add byte [ebp], 1\n    lea edi, [ebp + 1]\n"));
+    assert!(asm.contains(
+        "    add eax, 30000\n    cmp edi, eax\n    jb wrapped_0\n    call wrap_addr\nwrapped_0:\n"
+    ));
+    assert!(asm.contains("    cmp edi, eax\n    jae wrapped_1\n    call wrap_addr\nwrapped_1:\n"));
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+use brainfuck::{
+    bytecode::{ADD, JNZ, JZ, MOVE, PRINT},
+    BoundsPolicy, Bytecode, Config, Error, OverflowPolicy, Program, Runner,
+};
+
+fn bytecode(src: &str) -> Bytecode {
+    Program::parse(src).unwrap().bytecode()
+}
+
+#[test]
+fn loops_jump_past_each_other() {
+    let code = bytecode("+[>+[-]<-].");
+    assert_eq!(
+        *code.code(),
+        [
+            ADD, 0, 1, // 0
+            JZ, 24, // 3
+            ADD, 1, 1, // 5
+            MOVE, 1, // 8
+            JZ, 17, // 10
+            ADD, 0, -1, // 12
+            JNZ, 12, // 15
+            ADD, -1, -1, // 17
+            MOVE, -1, // 20
+            JNZ, 5, // 22
+            PRINT, 0, // 24
+        ]
+    );
+}
+
+#[test]
+fn lists_each_instruction_with_its_position() {
+    assert_eq!(
+        bytecode(">>,<.").to_string(),
+        "0000  read 2\n0002  print 1\n0004  move 1\n"
+    );
+}
+
+#[test]
+fn finds_the_code_behind_an_instruction() {
+    let code = bytecode("+\n>.");
+    let print = code.code().iter().position(|&op| op == PRINT).unwrap();
+    assert_eq!(code.span_at(print).to_string(), "line 2, col 2");
+    assert_eq!(code.span_at(print + 1).to_string(), "line 2, col 2");
+    assert_eq!(code.span_at(0).to_string(), "line 1, col 1");
+}
+
+#[test]
+fn traps_are_the_ir_engines_errors() {
+    for bounds in [BoundsPolicy::Trap, BoundsPolicy::Grow] {
+        let config = Config {
+            bounds,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        };
+        for src in ["+>\n-", "+>>+", "<<+>-", "+[-]-"] {
+            let program = Program::parse(src).unwrap().with_config(config);
+            let ir = program.run_ir_with(io::empty(), io::sink());
+            let vm = program.run_with(io::empty(), io::sink());
+            assert_eq!(
+                format!("{:?}", ir),
+                format!("{:?}", vm),
+                "{:?} {}",
+                bounds,
+                src
+            );
+        }
+    }
+}
+
+#[test]
+fn runs_continue_on_the_same_tape() {
+    let mut runner = Runner::with_io(vec![], vec![], io::empty(), Vec::new());
+    runner.run_bytecode(&bytecode("++++++++")).unwrap();
+    runner.run_bytecode(&bytecode("[>++++++<-]>.")).unwrap();
+    assert_eq!(runner.into_output().unwrap(), b"0");
+}
+
+#[test]
+fn write_errors_stop_the_program() {
+    struct Broken;
+    impl io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("unplugged"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Prints forever, until the buffer fills and has to be written out.
+    let program = Program::parse("+[.]").unwrap();
+    let err = program.run_with(io::empty(), Broken).unwrap_err();
+    assert!(matches!(err, Error::Io(ref e) if e.to_string() == "unplugged"));
+}
@@ -0,0 +1,99 @@
+use std::process::{Command, Stdio};
+
+use brainfuck::{
+    BoundsPolicy, CellWidth, CodegenError, Config, EofPolicy, Error, OverflowPolicy, Program,
+};
+
+fn c_source(src: &str, config: Config) -> String {
+    Program::parse(src)
+        .unwrap()
+        .with_config(config)
+        .c_source()
+        .unwrap()
+}
+
+#[test]
+fn options_show_up_in_the_source() {
+    let c = c_source(
+        ",>.",
+        Config {
+            cell: CellWidth::U16,
+            tape_len: 100,
+            eof: EofPolicy::Max,
+            ..Default::default()
+        },
+    );
+    assert!(c.contains("#define TAPE_LEN 100\n"));
+    assert!(c.contains("typedef uint16_t cell;\n"));
+    assert!(c.contains("else *c = CELL_MAX;"));
+    assert!(c.contains("    p = seek(1);\n"));
+}
+
+#[test]
+fn unused_helpers_are_left_out() {
+    let c = c_source("+.", Config::default());
+    assert!(!c.contains("seek"));
+    assert!(!c.contains("input"));
+    assert!(c.contains("    tape[p] += 1;\n    putchar(tape[p]);\n"));
+}
+
+#[test]
+fn loops_are_indented() {
+    let c = c_source("+[[-]]", Config::default());
+    assert!(c.contains("    while (tape[p]) {\n        while (tape[p]) {\n"));
+    assert!(c.contains("\n        }\n    }\n"));
+}
+
+#[test]
+fn unbounded_cells_are_unsupported() {
+    let program = Program::parse("+.").unwrap().with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    });
+    let err = program.c_source().unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+}
+
+#[test]
+fn traps_report_like_the_interpreter() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        return;
+    }
+    for bounds in [BoundsPolicy::Trap, BoundsPolicy::Grow] {
+        let config = Config {
+            bounds,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        };
+        for src in ["+>\n-", "+>>+", "<<+>-"] {
+            let program = Program::parse(src).unwrap().with_config(config);
+            let expected = match program.run_with(std::io::empty(), std::io::sink()) {
+                Ok(()) => continue, // grows instead
+                Err(e) => format!("{}\n", e),
+            };
+
+            let base = std::env::temp_dir().join(format!(
+                "cgen-trap-{:?}-{}-{}",
+                bounds,
+                src.len(),
+                std::process::id()
+            ));
+            let source = base.with_extension("c");
+            std::fs::write(&source, program.c_source().unwrap()).unwrap();
+            let status = Command::new("cc")
+                .arg(&source)
+                .arg("-o")
+                .arg(&base)
+                .status()
+                .unwrap();
+            std::fs::remove_file(&source).unwrap();
+            assert!(status.success());
+
+            let output = Command::new(&base).stdin(Stdio::null()).output().unwrap();
+            std::fs::remove_file(&base).unwrap();
+            assert_eq!(output.status.code(), Some(1), "{:?} {}", bounds, src);
+            assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use brainfuck::{
+    BoundsPolicy, Config, Diagnostic, Error, OverflowPolicy, Program, RuntimeError, Span,
+};
+
+fn render(src: &str, config: Config) -> String {
+    colored::control::set_override(false);
+    let err = match Program::parse(src) {
+        Ok(program) => program
+            .with_config(config)
+            .run_tokens_with(&b""[..], Vec::new())
+            .unwrap_err(),
+        Err(err) => err,
+    };
+    Diagnostic::new(&err, "test.bf", src).to_string()
+}
+
+#[test]
+fn unclosed_loops_point_at_the_opening_bracket() {
+    assert_eq!(
+        render("+\n+[>[-]", Config::default()),
+        "\
+error: unclosed `[`
+ --> test.bf:2:2
+  |
+2 | +[>[-]
+  |  ^ this loop is never closed
+  |
+  = help: add a `]` where the loop should end
+"
+    );
+}
+
+#[test]
+fn carets_line_up_with_tabs() {
+    let text = render("+[-]\n\t ]", Config::default());
+    assert!(
+        text.contains("2 | \t ]\n  | \t ^ there is no `[`"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn lex_errors_show_the_character() {
+    let text = render("+\n\n\n\n\n\n\n\n\n++a", Config::default());
+    assert!(text.starts_with("error: unexpected character `a`\n  --> test.bf:10:3\n"));
+    assert!(
+        text.contains("10 | ++a\n   |   ^ not brainfuck"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn runtime_traps_have_hints() {
+    let config = Config {
+        bounds: BoundsPolicy::Trap,
+        ..Default::default()
+    };
+    let text = render("<", config);
+    assert!(text.contains("^ moves to cell -1, off the 30000 cell tape"));
+    assert!(text.contains("= help: use `--bounds grow`"));
+
+    let config = Config {
+        overflow: OverflowPolicy::Trap,
+        ..Default::default()
+    };
+    let text = render("+>-", config);
+    assert!(
+        text.contains("1 | +>-\n  |   ^ takes cell 1 below 0"),
+        "{}",
+        text
+    );
+    assert!(text.contains("= help: use `--overflow wrap`"));
+}
+
+#[test]
+fn full_tapes_point_at_the_interpreters() {
+    colored::control::set_override(false);
+    let err = Error::Runtime(RuntimeError::TapeFull {
+        span: Span {
+            pos: 1,
+            line: 1,
+            col: 2,
+            len: 1,
+        },
+        cell: 1 << 30,
+    });
+    let text = Diagnostic::new(&err, "test.bf", "+>").to_string();
+    assert!(
+        text.contains("1 | +>\n  |  ^ moves to cell 1073741824, further than the tape can grow"),
+        "{}",
+        text
+    );
+    assert!(text.contains("use `--engine bytecode`"));
+}
+
+#[test]
+fn all_errors_are_shown_with_a_count() {
+    let text = render("[\n]]\n?", Config::default());
+    assert_eq!(text.matches("^").count(), 2);
+    assert!(
+        text.contains("error: unmatched `]`\n --> test.bf:2:2"),
+        "{}",
+        text
+    );
+    assert!(text.contains("error: unexpected character `?`\n --> test.bf:3:1"));
+    assert!(
+        text.ends_with("\nerror: aborting due to 2 previous errors\n"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn long_spans_are_underlined() {
+    let text = render("+ /* never\n closed", Config::default());
+    assert!(
+        text.contains("1 | + /* never\n  |   ^^ this comment is never closed"),
+        "{}",
+        text
+    );
+}
@@ -0,0 +1,160 @@
+use std::process::Command;
+
+use brainfuck::{
+    x86::{self, Alu, Cond, Line, Mem, Op, Reg, Size},
+    BoundsPolicy, CellWidth, Config, OverflowPolicy, Program, Target,
+};
+
+fn executable(src: &str, config: Config) -> Vec<u8> {
+    Program::parse(src)
+        .unwrap()
+        .with_config(config)
+        .executable()
+        .unwrap()
+}
+
+#[test]
+fn headers_match_the_target() {
+    let exe = executable("+.", Config::default());
+    assert_eq!(&exe[..4], b"\x7fELF");
+    assert_eq!((exe[4], exe[18]), (1, 0x03)); // 32-bit, i386
+
+    let config = Config {
+        target: Target::X86_64,
+        ..Default::default()
+    };
+    let exe = executable("+.", config);
+    assert_eq!(&exe[..4], b"\x7fELF");
+    assert_eq!((exe[4], exe[18]), (2, 0x3e)); // 64-bit, x86-64
+}
+
+fn encode(op: Op, target: Target) -> Vec<u8> {
+    x86::encode(&[Line::Op(op, None)], target).unwrap().bytes
+}
+
+#[test]
+fn encodes_like_an_assembler() {
+    let cell = Mem::Base(Reg::Rbp, 0);
+    let op = Op::Alu(Alu::Add, Size::Word, cell.clone().into(), 1.into());
+    assert_eq!(encode(op, Target::X86_64), [0x66, 0x83, 0x45, 0x00, 0x01]);
+    let op = Op::Alu(Alu::Add, Size::Byte, cell.clone().into(), 255.into());
+    assert_eq!(encode(op, Target::X86), [0x80, 0x45, 0x00, 0xff]);
+    let op = Op::Lea(Reg::Rbx, Mem::Base(Reg::Rbp, -2));
+    assert_eq!(encode(op, Target::X86_64), [0x48, 0x8d, 0x5d, 0xfe]);
+    let op = Op::Mov(Size::Qword, Reg::Rbp.into(), Reg::Rbx.into());
+    assert_eq!(encode(op, Target::X86_64), [0x48, 0x89, 0xdd]);
+    let op = Op::Mov(Size::Dword, Reg::Rdi.into(), (-1).into());
+    assert_eq!(encode(op, Target::X86), [0xbf, 0xff, 0xff, 0xff, 0xff]);
+    let op = Op::Mov(Size::Qword, Reg::R10.into(), 3.into());
+    assert_eq!(encode(op, Target::X86_64), [0x49, 0xc7, 0xc2, 3, 0, 0, 0]);
+    assert_eq!(encode(Op::Syscall, Target::X86_64), [0x0f, 0x05]);
+    assert_eq!(encode(Op::Push(Reg::R12), Target::X86_64), [0x41, 0x54]);
+    assert_eq!(encode(Op::Pop(Reg::Rbp), Target::X86_64), [0x5d]);
+    let op = Op::CallPtr(Mem::Base(Reg::R14, 8));
+    assert_eq!(encode(op, Target::X86_64), [0x41, 0xff, 0x56, 0x08]);
+}
+
+#[test]
+fn labels_are_linked() {
+    let lines = [
+        Line::Label("top".to_string()),
+        Line::Op(Op::J(Cond::Ne, "top".to_string()), None),
+        Line::Op(Op::Lea(Reg::Rbp, Mem::Label("tape".to_string())), None),
+    ];
+    let symbols = [("tape".to_string(), 0x1000)].into_iter().collect();
+
+    let mut code = x86::encode(&lines, Target::X86_64).unwrap();
+    code.link(0x100, &symbols).unwrap();
+    // Relative to the end of each instruction: 0x106 and 0x10d.
+    assert_eq!(
+        code.bytes,
+        [0x0f, 0x85, 0xfa, 0xff, 0xff, 0xff, 0x48, 0x8d, 0x2d, 0xf3, 0x0e, 0, 0]
+    );
+
+    let mut code = x86::encode(&lines, Target::X86).unwrap();
+    code.link(0x100, &symbols).unwrap();
+    assert_eq!(&code.bytes[6..], [0x8d, 0x2d, 0x00, 0x10, 0, 0]);
+
+    let mut code = x86::encode(&lines[1..], Target::X86).unwrap();
+    assert!(code.link(0x100, &symbols).is_err());
+}
+
+#[test]
+fn compiled_traps_report_and_fail() {
+    for target in [Target::X86, Target::X86_64] {
+        let config = Config {
+            target,
+            bounds: BoundsPolicy::Trap,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        };
+        for (src, message) in [
+            ("+>\n-", "Cell overflow at line 2, col 1: cell went below 0"),
+            ("+>>+", "Pointer out of bounds at line 1, col 3"),
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "elf-trap-{:?}-{}-{}",
+                target,
+                src.len(),
+                std::process::id()
+            ));
+            std::fs::write(&path, executable(src, config)).unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            }
+
+            let output = Command::new(&path).output();
+            std::fs::remove_file(&path).unwrap();
+            let output = match output {
+                Err(e) if e.raw_os_error() == Some(8) => continue, // can't run x86 here
+                output => output.unwrap(),
+            };
+            assert_eq!(output.status.code(), Some(1), "{:?} {}", target, src);
+            assert_eq!(
+                String::from_utf8_lossy(&output.stderr),
+                format!("Runtime error: {}\n", message)
+            );
+        }
+    }
+}
+
+#[test]
+fn growing_past_the_mapping_traps() {
+    // Each trip round the loop moves 1 MiB, so the second half of the
+    // mapping runs out after 512 of them, as it does for the JIT.
+    let src = format!("+[{}+]", ">".repeat(1 << 18));
+    for target in [Target::X86, Target::X86_64] {
+        let config = Config {
+            target,
+            cell: CellWidth::U32,
+            bounds: BoundsPolicy::Grow,
+            ..Default::default()
+        };
+        let path =
+            std::env::temp_dir().join(format!("elf-full-{:?}-{}", target, std::process::id()));
+        std::fs::write(&path, executable(&src, config)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let output = Command::new(&path).output();
+        std::fs::remove_file(&path).unwrap();
+        let output = match output {
+            Err(e) if e.raw_os_error() == Some(8) => continue, // can't run x86 here
+            output => output.unwrap(),
+        };
+        assert_eq!(output.status.code(), Some(1), "{:?}", target);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            format!(
+                "Runtime error: Tape full at line 1, col {}\n",
+                src.len() - 1
+            )
+        );
+    }
+}
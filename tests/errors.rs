@@ -0,0 +1,114 @@
+use std::error::Error as _;
+use std::io::{self, Write};
+
+use brainfuck::{
+    CellWidth, CodegenError, Config, Error, LexError, Lexer, Program, SyntaxError, SyntaxParser,
+};
+
+fn parse_err(src: &str) -> Error {
+    Program::parse(src).unwrap_err()
+}
+
+#[test]
+fn unmatched_loop_ends_point_at_the_bracket() {
+    let err = parse_err("+[-]\n  ]");
+    let Error::Syntax(SyntaxError::UnmatchedLoopEnd { span }) = err else {
+        panic!("{}", err);
+    };
+    assert_eq!((span.pos, span.line, span.col), (7, 2, 3));
+}
+
+#[test]
+fn unmatched_loop_starts_point_at_the_bracket() {
+    let err = parse_err("+[-]\n[>[-]");
+    assert!(matches!(
+        err,
+        Error::Syntax(SyntaxError::UnmatchedLoopStart { .. })
+    ));
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (2, 1));
+    assert_eq!(
+        err.to_string(),
+        "Syntax error: Unmatched LoopStart at line 2, col 1"
+    );
+}
+
+#[test]
+fn stages_report_their_own_errors() {
+    let mut lexer = Lexer::new("++\n+x".to_string());
+    let [LexError::UnexpectedChar { ch, span }] = lexer.parse().unwrap_err()[..] else {
+        panic!("expected a single error");
+    };
+    assert_eq!((ch, span.line, span.col), ('x', 2, 2));
+
+    let mut lexer = Lexer::new("[]]".to_string());
+    lexer.parse().unwrap();
+    let err = SyntaxParser::new().parse(lexer.tokens()).unwrap_err();
+    assert_eq!(err.len(), 1);
+    assert_eq!(err[0].span().col, 3);
+}
+
+#[test]
+fn unsupported_options_are_codegen_errors() {
+    let program = Program::parse("+.").unwrap().with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    });
+    let err = program.assembly().unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+    assert_eq!(err.span(), None);
+}
+
+struct Broken;
+
+impl Write for Broken {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("disk on fire"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::Error::other("disk on fire"))
+    }
+}
+
+#[test]
+fn io_errors_keep_their_cause() {
+    let err = Program::parse("+.")
+        .unwrap()
+        .run_with(io::empty(), Broken)
+        .unwrap_err();
+    assert!(matches!(err, Error::Io(_)));
+    assert_eq!(err.source().unwrap().to_string(), "disk on fire");
+}
+
+#[test]
+fn every_error_is_reported_in_source_order() {
+    let err = parse_err("]+a\n[-]\n#][");
+    let Error::Multiple(errors) = &err else {
+        panic!("{}", err);
+    };
+    let found: Vec<_> = errors
+        .iter()
+        .map(|e| {
+            let span = e.span().unwrap();
+            let kind = match e {
+                Error::Lex(_) => "lex",
+                Error::Syntax(SyntaxError::UnmatchedLoopEnd { .. }) => "]",
+                Error::Syntax(SyntaxError::UnmatchedLoopStart { .. }) => "[",
+                _ => "other",
+            };
+            (kind, span.line, span.col)
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("]", 1, 1),
+            ("lex", 1, 3),
+            ("lex", 3, 1),
+            ("]", 3, 2),
+            ("[", 3, 3)
+        ]
+    );
+    assert!(err.to_string().ends_with("5 errors"));
+}
@@ -0,0 +1,169 @@
+use brainfuck::{BoundsPolicy, Config, Instruction, OverflowPolicy, Program};
+
+fn lower(src: &str) -> Vec<Instruction> {
+    Program::parse(src).unwrap().optimize().instructions
+}
+
+// Straightforward evaluation of the IR on a wrapping 30000 cell tape.
+fn eval(ins: &[Instruction]) -> (Vec<u8>, usize, Vec<u8>) {
+    let mut tape = vec![0u8; 30000];
+    let mut ptr = 0usize;
+    let mut out = vec![];
+    let at = |ptr: usize, offset: isize| (ptr as isize + offset).rem_euclid(30000) as usize;
+
+    let mut i = 0;
+    while i < ins.len() {
+        match ins[i] {
+            Instruction::Add { offset, delta } => {
+                let c = at(ptr, offset);
+                tape[c] = tape[c].wrapping_add(delta as u8);
+            }
+            Instruction::Move(n) => ptr = at(ptr, n),
+            Instruction::Print { offset } => out.push(tape[at(ptr, offset)]),
+            Instruction::Read { .. } => unreachable!(),
+            Instruction::LoopStart if tape[ptr] == 0 => {
+                let mut depth = 1;
+                while depth != 0 {
+                    i += 1;
+                    match ins[i] {
+                        Instruction::LoopStart => depth += 1,
+                        Instruction::LoopEnd => depth -= 1,
+                        _ => {}
+                    }
+                }
+            }
+            Instruction::LoopEnd if tape[ptr] != 0 => {
+                let mut depth = 1;
+                while depth != 0 {
+                    i -= 1;
+                    match ins[i] {
+                        Instruction::LoopStart => depth -= 1,
+                        Instruction::LoopEnd => depth += 1,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    (tape, ptr, out)
+}
+
+#[test]
+fn folds_moves_into_offsets() {
+    assert_eq!(
+        lower("+>++>---<."),
+        vec![
+            Instruction::Add {
+                offset: 0,
+                delta: 1
+            },
+            Instruction::Add {
+                offset: 1,
+                delta: 2
+            },
+            Instruction::Add {
+                offset: 2,
+                delta: -3
+            },
+            Instruction::Print { offset: 1 },
+            Instruction::Move(1),
+        ]
+    );
+}
+
+#[test]
+fn settles_pointer_at_loop_boundaries() {
+    assert_eq!(
+        lower(">+[>]<."),
+        vec![
+            Instruction::Add {
+                offset: 1,
+                delta: 1
+            },
+            Instruction::Move(1),
+            Instruction::LoopStart,
+            Instruction::Move(1),
+            Instruction::LoopEnd,
+            Instruction::Print { offset: -1 },
+            Instruction::Move(-1),
+        ]
+    );
+}
+
+#[test]
+fn scan_loops_move_the_pointer() {
+    // Fill cells 0..=4, scan right to the first zero, then back left past
+    // the origin.
+    let (tape, ptr, out) = eval(&lower(">+>+>+>+<<<<+[>]<<[<]>."));
+    assert_eq!(&tape[..6], &[1, 1, 1, 1, 1, 0]);
+    assert_eq!(ptr, 0);
+    assert_eq!(out, vec![1]);
+}
+
+#[test]
+fn unbalanced_loop_bodies_address_the_right_cells() {
+    // Each iteration of the outer loop shifts the counter one cell to the
+    // right, so the `-` and `.` inside it must follow the pointer instead of
+    // being pinned to a fixed cell.
+    let (tape, ptr, out) = eval(&lower("+++[[->+<]>-.]"));
+    assert_eq!(out, vec![2, 1, 0]);
+    assert_eq!(ptr, 3);
+    assert_eq!(&tape[..4], &[0, 0, 0, 0]);
+}
+
+#[test]
+fn moving_left_of_the_origin_wraps() {
+    let (tape, ptr, _) = eval(&lower("<+<++"));
+    assert_eq!(ptr, 29998);
+    assert_eq!(&tape[29998..], &[2, 1]);
+}
+
+#[test]
+fn trapping_overflow_splits_runs_that_turn_around() {
+    let program = Program::parse("+--+>-+").unwrap().with_config(Config {
+        overflow: OverflowPolicy::Trap,
+        ..Default::default()
+    });
+    let deltas: Vec<_> = program
+        .optimize()
+        .instructions
+        .iter()
+        .filter_map(|ins| match ins {
+            Instruction::Add { offset, delta } => Some((*offset, *delta)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(deltas, [(0, 1), (0, -2), (0, 1), (1, -1), (1, 1)]);
+    // Wrapping cells can fold them away.
+    assert_eq!(lower("+--+"), []);
+}
+
+#[test]
+fn trapping_bounds_take_every_step_on_its_own() {
+    let program = Program::parse("<>+>+<<.").unwrap().with_config(Config {
+        bounds: BoundsPolicy::Trap,
+        ..Default::default()
+    });
+    assert_eq!(
+        program.optimize().instructions,
+        vec![
+            Instruction::Move(-1),
+            Instruction::Move(1),
+            Instruction::Add {
+                offset: 0,
+                delta: 1
+            },
+            Instruction::Move(1),
+            Instruction::Add {
+                offset: 0,
+                delta: 1
+            },
+            Instruction::Move(-1),
+            Instruction::Move(-1),
+            Instruction::Print { offset: 0 },
+        ]
+    );
+}
@@ -0,0 +1,138 @@
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+use std::io::{self, Write};
+
+use brainfuck::{
+    BoundsPolicy, CellWidth, CodegenError, Config, EofPolicy, Error, JitCompiler, OverflowPolicy,
+    Program, RuntimeError,
+};
+
+fn jit(src: &str, config: Config, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let program = Program::parse(src).unwrap().with_config(config);
+    let mut out = Vec::new();
+    program.run_jit_with(input, &mut out)?;
+    Ok(out)
+}
+
+#[test]
+fn runs_hello_world() {
+    let src = std::fs::read_to_string("hello-world.bf").unwrap();
+    let out = jit(&src, Config::default(), b"").unwrap();
+    assert_eq!(out, b"Hello, World!\n");
+}
+
+#[test]
+fn options_agree_with_the_interpreter() {
+    let src = ",[>+<-]>+.<,.>>>,.<<<<<<<<<+.";
+    for config in [
+        Config::default(),
+        Config {
+            cell: CellWidth::U16,
+            eof: EofPolicy::Max,
+            ..Default::default()
+        },
+        Config {
+            cell: CellWidth::U32,
+            eof: EofPolicy::Zero,
+            bounds: BoundsPolicy::Grow,
+            ..Default::default()
+        },
+        Config {
+            tape_len: 5,
+            ..Default::default()
+        },
+    ] {
+        let program = Program::parse(src).unwrap().with_config(config);
+        let mut expected = Vec::new();
+        program.run_with(&b"Az"[..], &mut expected).unwrap();
+        assert_eq!(jit(src, config, b"Az").unwrap(), expected, "{:?}", config);
+    }
+}
+
+#[test]
+fn traps_are_the_interpreters_errors() {
+    for bounds in [BoundsPolicy::Trap, BoundsPolicy::Grow] {
+        let config = Config {
+            bounds,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        };
+        for src in ["+>\n-", "+>>+", "<<+>-", "+.>>."] {
+            let program = Program::parse(src).unwrap().with_config(config);
+            let mut expected = Vec::new();
+            let Err(e) = program.run_with(io::empty(), &mut expected) else {
+                continue; // grows instead
+            };
+            let mut out = Vec::new();
+            let err = program.run_jit_with(io::empty(), &mut out).unwrap_err();
+            assert_eq!(err.to_string(), e.to_string(), "{:?} {}", bounds, src);
+            assert_eq!(err.span(), e.span());
+            assert_eq!(out, expected, "output before the trap");
+        }
+    }
+}
+
+#[test]
+fn growing_past_the_mapping_is_an_error() {
+    // Each trip round the loop moves 1 MiB, so the second half of the tape
+    // runs out after 512 of them, touching a page each time.
+    let config = Config {
+        cell: CellWidth::U32,
+        bounds: BoundsPolicy::Grow,
+        ..Default::default()
+    };
+    let step = ">".repeat(1 << 18);
+    for src in [
+        format!("+[{}+]", step),
+        format!("+[<{}+]", step.replace('>', "<")),
+    ] {
+        let err = jit(&src, config, b"").unwrap_err();
+        let Error::Runtime(RuntimeError::TapeFull { span, cell }) = err else {
+            panic!("{}", err);
+        };
+        // The move is folded into the `+`, which is what leaves.
+        assert_eq!((span.line, span.col), (1, src.len() - 1));
+        assert!(cell.unsigned_abs() >= 1 << 27, "{}", cell);
+    }
+}
+
+#[test]
+fn programs_run_again_on_a_fresh_tape() {
+    let rep = Program::parse("+.").unwrap().optimize();
+    let program = JitCompiler::new().compile(&rep).unwrap();
+    for _ in 0..2 {
+        let mut out = Vec::new();
+        program.run_with(io::empty(), &mut out).unwrap();
+        assert_eq!(out, [1]);
+    }
+}
+
+struct Broken;
+
+impl Write for Broken {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("broken"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::Error::other("broken"))
+    }
+}
+
+#[test]
+fn io_errors_stop_the_program() {
+    let program = Program::parse("+[.,]").unwrap();
+    let err = program.run_jit_with(io::empty(), Broken).unwrap_err();
+    assert!(matches!(err, Error::Io(e) if e.to_string() == "broken"));
+}
+
+#[test]
+fn unbounded_cells_are_unsupported() {
+    let config = Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    };
+    let err = jit("+.", config, b"").unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+}
@@ -0,0 +1,168 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use brainfuck::{
+    BoundsPolicy, CellWidth, CodegenError, Config, EofPolicy, Error, OverflowPolicy, Program,
+};
+
+fn js_source(src: &str, config: Config) -> String {
+    Program::parse(src)
+        .unwrap()
+        .with_config(config)
+        .js_source()
+        .unwrap()
+}
+
+fn node_installed() -> bool {
+    Command::new("node").arg("--version").output().is_ok()
+}
+
+#[test]
+fn options_show_up_in_the_source() {
+    let js = js_source(
+        ",>.",
+        Config {
+            cell: CellWidth::U16,
+            tape_len: 100,
+            eof: EofPolicy::Max,
+            ..Default::default()
+        },
+    );
+    assert!(js.contains("const TAPE_LEN = 100;\n"));
+    assert!(js.contains("let tape = new Uint16Array(TAPE_LEN);\n"));
+    assert!(js.contains("else tape[i] = CELL_MAX;"));
+    assert!(js.contains("    read(0);\n    print(1);\n    p = seek(1);\n"));
+}
+
+#[test]
+fn unused_helpers_are_left_out() {
+    let js = js_source("+.", Config::default());
+    assert!(!js.contains("function read"));
+    assert!(!js.contains("function reader"));
+    assert!(js.contains("    add(0, 1);\n    print(0);\n"));
+}
+
+#[test]
+fn unbounded_cells_are_unsupported() {
+    let program = Program::parse("+.").unwrap().with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    });
+    let err = program.js_source().unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+}
+
+#[test]
+fn modules_take_input_and_output_from_the_caller() {
+    if !node_installed() {
+        return;
+    }
+    let module = std::env::temp_dir().join(format!("jsgen-embed-{}.mjs", std::process::id()));
+    std::fs::write(
+        &module,
+        js_source(
+            ",[+.,]",
+            Config {
+                eof: EofPolicy::Zero,
+                ..Default::default()
+            },
+        ),
+    )
+    .unwrap();
+
+    // Input as a string, a function and an array, and output as a callback
+    // or what `run` returns.
+    let script = "
+        const { run } = await import(process.argv[1]);
+        const seen = [];
+        run({ input: 'ab', output: (byte) => seen.push(byte) });
+        const bytes = [1, 2];
+        seen.push(...run({ input: () => bytes.shift() ?? null }));
+        seen.push(...run({ input: [7] }));
+        console.log(seen.join(' '));
+    ";
+    let output = Command::new("node")
+        .args(["--input-type=module", "-e", script])
+        .arg(&module)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&module).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"98 99 2 3 8\n");
+}
+
+#[test]
+fn printed_bytes_show_up_while_the_program_runs() {
+    if !node_installed() {
+        return;
+    }
+    // Prints `A`, then never stops.
+    let module = std::env::temp_dir().join(format!("jsgen-live-{}.mjs", std::process::id()));
+    std::fs::write(
+        &module,
+        js_source("++++++++[>++++++++<-]>+.+[]", Config::default()),
+    )
+    .unwrap();
+
+    let mut child = Command::new("node")
+        .arg(&module)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0];
+        let _ = sender.send(stdout.read_exact(&mut byte).map(|()| byte[0]).ok());
+    });
+    let printed = receiver.recv_timeout(Duration::from_secs(10));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&module).unwrap();
+    assert_eq!(printed, Ok(Some(b'A')));
+}
+
+#[test]
+fn traps_report_like_the_interpreter() {
+    if !node_installed() {
+        return;
+    }
+    for bounds in [BoundsPolicy::Trap, BoundsPolicy::Grow] {
+        let config = Config {
+            bounds,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        };
+        for src in ["+>\n-", "+>>+", "<<+>-"] {
+            let program = Program::parse(src).unwrap().with_config(config);
+            let expected = match program.run_with(std::io::empty(), std::io::sink()) {
+                Ok(()) => continue, // grows instead
+                Err(e) => format!("{}\n", e),
+            };
+
+            let module = std::env::temp_dir().join(format!(
+                "jsgen-trap-{:?}-{}-{}.mjs",
+                bounds,
+                src.len(),
+                std::process::id()
+            ));
+            std::fs::write(&module, program.js_source().unwrap()).unwrap();
+            let output = Command::new("node")
+                .arg(&module)
+                .stdin(Stdio::null())
+                .output()
+                .unwrap();
+            std::fs::remove_file(&module).unwrap();
+            assert_eq!(output.status.code(), Some(1), "{:?} {}", bounds, src);
+            assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+        }
+    }
+}
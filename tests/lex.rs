@@ -0,0 +1,218 @@
+use std::io::{self, BufReader, Read};
+
+use brainfuck::{Error, LexError, LexMode, Lexer, Program, Runner, SyntaxParser, TokenType};
+
+fn lex(src: &str, mode: LexMode) -> Result<Vec<(TokenType, usize, usize)>, Vec<LexError>> {
+    let mut lexer = Lexer::new(src.to_string()).with_mode(mode);
+    lexer.parse()?;
+    Ok(lexer
+        .tokens()
+        .iter()
+        .map(|t| (t.token_type(), t.line(), t.col()))
+        .collect())
+}
+
+// Columns of the characters `parse` rejected.
+fn rejected(src: &str, mode: LexMode) -> Vec<usize> {
+    lex(src, mode)
+        .unwrap_err()
+        .iter()
+        .map(|e| e.span().col)
+        .collect()
+}
+
+#[test]
+fn standard_mode_ignores_everything_else() {
+    let tokens = lex("a+ b/-\n# [.]!", LexMode::Standard).unwrap();
+    assert_eq!(
+        tokens,
+        [
+            (TokenType::Increment, 1, 2),
+            (TokenType::Decrement, 1, 6),
+            (TokenType::LoopStart, 2, 3),
+            (TokenType::Print, 2, 4),
+            (TokenType::LoopEnd, 2, 5),
+            (TokenType::Eof, 2, 7),
+        ]
+    );
+}
+
+#[test]
+fn comments_mode_allows_line_comments_only() {
+    let tokens = lex("+ // add, then print.\n.", LexMode::Comments).unwrap();
+    assert_eq!(
+        tokens,
+        [
+            (TokenType::Increment, 1, 1),
+            (TokenType::Print, 2, 1),
+            (TokenType::Eof, 2, 2),
+        ]
+    );
+    assert_eq!(rejected("+ add", LexMode::Comments), [3, 4, 5]);
+}
+
+#[test]
+fn strict_mode_allows_whitespace_only() {
+    assert!(lex(" +\t-\r\n.", LexMode::Strict).is_ok());
+    assert_eq!(rejected("+ //x", LexMode::Strict), [3, 4, 5]);
+}
+
+#[test]
+fn comments_mode_is_the_default() {
+    let mut lexer = Lexer::new("+ // note".to_string());
+    assert!(lexer.parse().is_ok());
+}
+
+#[test]
+fn block_comments_nest_and_span_lines() {
+    let tokens = lex("+/* a /* [ */ ]\n */-", LexMode::Comments).unwrap();
+    assert_eq!(
+        tokens,
+        [
+            (TokenType::Increment, 1, 1),
+            (TokenType::Decrement, 2, 4),
+            (TokenType::Eof, 2, 5),
+        ]
+    );
+}
+
+#[test]
+fn unterminated_block_comments_point_at_their_start() {
+    let errors = lex("+\n /* /* */ +", LexMode::Comments).unwrap_err();
+    let [LexError::UnterminatedComment { span }] = errors[..] else {
+        panic!("{:?}", errors);
+    };
+    assert_eq!((span.line, span.col, span.len), (2, 2, 2));
+}
+
+#[test]
+fn lone_slashes_are_errors() {
+    assert_eq!(rejected("+/+", LexMode::Comments), [2]);
+    assert_eq!(rejected("+/", LexMode::Comments), [2]);
+    assert!(lex("+/+", LexMode::Standard).is_ok());
+}
+
+#[test]
+fn annotations_label_the_code_after_them() {
+    let src = "//@ set up\n++ // not an annotation\n/*@ print\n it */.>.\n/*@ done */";
+    let mut lexer = Lexer::new(src.to_string());
+    lexer.parse().unwrap();
+
+    let annotations: Vec<_> = lexer
+        .annotations()
+        .iter()
+        .map(|a| (a.text.as_str(), a.span.line, a.tokens.clone()))
+        .collect();
+    assert_eq!(
+        annotations,
+        [
+            ("set up", 1, 0..2),
+            ("print\n it", 3, 2..5),
+            ("done", 5, 5..5)
+        ]
+    );
+}
+
+#[test]
+fn kept_comments_round_trip() {
+    let src = "+ // one\n/* two /* three */ */+.";
+    let mut lexer = Lexer::new(src.to_string()).keep_comments(true);
+    lexer.parse().unwrap();
+
+    let comments: Vec<String> = lexer
+        .tokens()
+        .iter()
+        .filter(|t| t.token_type() == TokenType::Comment)
+        .map(|t| {
+            let span = t.span();
+            src[span.pos..span.pos + span.len].to_string()
+        })
+        .collect();
+    assert_eq!(comments, ["// one", "/* two /* three */ */"]);
+
+    // They don't get in the way of running the program.
+    let mut syntax = SyntaxParser::new();
+    syntax.parse(lexer.tokens()).unwrap();
+    let mut runner = Runner::with_io(
+        lexer.tokens().clone(),
+        syntax.jumps().clone(),
+        std::io::empty(),
+        Vec::new(),
+    );
+    runner.run().unwrap();
+    assert_eq!(runner.into_output().unwrap(), [2]);
+}
+
+#[test]
+fn positions_count_bytes_and_columns_count_characters() {
+    let src = "é+ // ünïcode\n→-";
+    let mut lexer = Lexer::new(src.to_string()).with_mode(LexMode::Standard);
+    lexer.parse().unwrap();
+    let spans: Vec<_> = lexer
+        .tokens()
+        .iter()
+        .map(|t| (t.pos(), t.line(), t.col()))
+        .collect();
+    assert_eq!(spans, [(2, 1, 2), (20, 2, 2), (21, 2, 3)]);
+
+    let errors = lex("+é→\n€", LexMode::Strict).unwrap_err();
+    let found: Vec<_> = errors
+        .iter()
+        .map(|e| match e {
+            LexError::UnexpectedChar { ch, span } => (*ch, span.pos, span.col, span.len),
+            _ => panic!("{:?}", e),
+        })
+        .collect();
+    assert_eq!(found, [('é', 1, 2, 2), ('→', 3, 3, 3), ('€', 7, 1, 3)]);
+}
+
+#[test]
+fn invalid_utf8_is_reported_not_panicked_on() {
+    let mut lexer = Lexer::default();
+    let errors = match lexer.parse_from(&b"+\xff\xe2\x86-"[..]) {
+        Err(Error::Multiple(errors)) => errors,
+        other => panic!("{:?}", other),
+    };
+    let spans: Vec<_> = errors.iter().map(|e| e.span().unwrap().pos).collect();
+    assert_eq!(spans, [1, 2]);
+    assert!(errors[0].to_string().contains('\u{fffd}'));
+}
+
+#[test]
+fn streaming_matches_lexing_a_string() {
+    let src = "//@ start\n+[->+<]/* nested /* é */ */\n//@ end\n.,";
+    let mut whole = Lexer::new(src.to_string()).keep_comments(true);
+    whole.parse().unwrap();
+
+    // One byte at a time, so every construct straddles a read.
+    let reader = BufReader::with_capacity(1, src.as_bytes());
+    let mut streamed = Lexer::default().keep_comments(true);
+    streamed.parse_from(reader).unwrap();
+
+    assert_eq!(streamed.tokens(), whole.tokens());
+    assert_eq!(streamed.annotations(), whole.annotations());
+}
+
+struct Failing;
+
+impl Read for Failing {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("unplugged"))
+    }
+}
+
+#[test]
+fn read_errors_are_passed_on() {
+    let err = Program::read_with(BufReader::new(Failing), LexMode::Standard).unwrap_err();
+    assert!(matches!(err, Error::Io(_)));
+}
+
+#[test]
+fn big_programs_lex_quickly() {
+    // Quadratic lexing would take minutes on this.
+    let src = "+>-<[.,]// comment\n".repeat(250_000);
+    let start = std::time::Instant::now();
+    let program = Program::read_with(src.as_bytes(), LexMode::Comments).unwrap();
+    assert_eq!(program.tokens().len(), 2_000_001);
+    assert!(start.elapsed().as_secs() < 10);
+}
@@ -0,0 +1,171 @@
+use std::io;
+use std::process::Command;
+
+use brainfuck::{
+    BoundsPolicy, CellWidth, CodegenError, Config, Dialect, EofPolicy, Error, OverflowPolicy,
+    Program, RuntimeError, Target,
+};
+
+// 16-bit cells on a 4 cell tape that wraps, with `,` storing the largest
+// value at the end of input.
+const CONFIG: Config = Config {
+    eof: EofPolicy::Max,
+    cell: CellWidth::U16,
+    tape_len: 4,
+    bounds: BoundsPolicy::Wrap,
+    overflow: OverflowPolicy::Wrap,
+    target: Target::X86_64,
+};
+
+// Prints the low byte of 65535 and of two bytes read, then comes back round
+// to cell 0 and takes it past 65535.
+const SRC: &str = "-.,.,.>>>>+.";
+
+// What every way of running `program` printed and returned, by name.
+fn runs(program: &Program, input: &[u8]) -> Vec<(&'static str, Vec<u8>, Result<(), Error>)> {
+    let mut runs = Vec::new();
+    let mut out = Vec::new();
+    let result = program.run_with(input, &mut out);
+    runs.push(("bytecode", out, result));
+    let mut out = Vec::new();
+    let result = program.run_ir_with(input, &mut out);
+    runs.push(("ir", out, result));
+    let mut out = Vec::new();
+    let result = program.run_tokens_with(input, &mut out);
+    runs.push(("tokens", out, result));
+    if cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        let mut out = Vec::new();
+        let result = program.run_jit_with(input, &mut out);
+        runs.push(("jit", out, result));
+    }
+    runs
+}
+
+#[test]
+fn every_engine_runs_with_the_config() {
+    let program = Program::parse(SRC).unwrap().with_config(CONFIG);
+    for (engine, out, result) in runs(&program, b"a") {
+        result.unwrap();
+        assert_eq!(out, [255, b'a', 255, 0], "{}", engine);
+    }
+}
+
+#[test]
+fn every_engine_reports_the_same_trap() {
+    // Each engine blames the `<` or `>` that leaves, and the cell it reached.
+    for (src, printed, line, col, cell) in [
+        ("+.>>>>.", &[1][..], 1, 6, 4),
+        ("<+", &[], 1, 1, -1),
+        ("+>>>\n<<<<<.", &[], 2, 4, -1),
+    ] {
+        let program = Program::parse(src).unwrap().with_config(Config {
+            bounds: BoundsPolicy::Trap,
+            ..CONFIG
+        });
+        for (engine, out, result) in runs(&program, b"") {
+            assert_eq!(out, printed, "{} {:?}", engine, src);
+            let Err(Error::Runtime(RuntimeError::OutOfBounds {
+                span,
+                cell: at,
+                tape_len,
+            })) = result
+            else {
+                panic!("{} didn't trap: {:?}", engine, result);
+            };
+            assert_eq!(
+                (span.line, span.col, at, tape_len),
+                (line, col, cell, 4),
+                "{} {:?}",
+                engine,
+                src
+            );
+        }
+    }
+
+    let program = Program::parse("+++\n-----").unwrap().with_config(Config {
+        overflow: OverflowPolicy::Trap,
+        ..CONFIG
+    });
+    for (engine, out, result) in runs(&program, b"") {
+        assert_eq!(out, [], "{}", engine);
+        let Err(Error::Runtime(RuntimeError::Overflow {
+            span, below_zero, ..
+        })) = result
+        else {
+            panic!("{} didn't trap: {:?}", engine, result);
+        };
+        assert_eq!(span.line, 2, "{}", engine);
+        assert!(below_zero, "{}", engine);
+    }
+}
+
+#[test]
+fn engines_hand_back_read_errors() {
+    struct Broken;
+    impl io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("unplugged"))
+        }
+    }
+
+    let program = Program::parse(",.").unwrap().with_config(CONFIG);
+    let mut out = Vec::new();
+    let results = [
+        program.run_with(Broken, &mut out),
+        program.run_ir_with(Broken, &mut out),
+        program.run_tokens_with(Broken, &mut out),
+    ];
+    for result in results {
+        assert!(matches!(result, Err(Error::Io(ref e)) if e.to_string() == "unplugged"));
+    }
+    assert_eq!(out, []);
+}
+
+#[test]
+fn assembly_follows_the_config() {
+    let program = Program::parse("+.").unwrap().with_config(CONFIG);
+    let nasm = program.assembly_in(Dialect::Nasm).unwrap();
+    assert!(nasm.starts_with("bits 64\n"), "{}", nasm);
+    assert!(nasm.contains("tape resw 4\n"), "{}", nasm);
+    assert!(nasm.contains("add word [rbp], 1\n"), "{}", nasm);
+    let att = program.assembly_in(Dialect::GasAtt).unwrap();
+    assert!(att.contains("addw $1, (%rbp)\n"), "{}", att);
+
+    let program = program.with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..CONFIG
+    });
+    let err = program.assembly_in(Dialect::Fasm).unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+}
+
+#[test]
+fn executables_follow_the_config() {
+    let program = Program::parse(SRC).unwrap().with_config(CONFIG);
+    let exe = program.executable().unwrap();
+    assert_eq!((exe[4], exe[18]), (2, 0x3e)); // 64-bit, x86-64
+
+    let err = program
+        .with_config(Config {
+            cell: CellWidth::Unbounded,
+            ..CONFIG
+        })
+        .executable()
+        .unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+
+    if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        return;
+    }
+    let path = std::env::temp_dir().join(format!("program-exe-{}", std::process::id()));
+    std::fs::write(&path, exe).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let output = Command::new(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [255, 255, 255, 0]);
+}
@@ -0,0 +1,451 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Runs `src` through the brainfuck binary and returns everything it printed.
+fn run(name: &str, src: &str, args: &[&str], input: &[u8]) -> Vec<u8> {
+    stdout_of(name, interpreted(name, src, args, input))
+}
+
+// The output of a run that has to succeed.
+fn stdout_of(name: &str, output: Output) -> Vec<u8> {
+    assert!(
+        output.status.success(),
+        "{} exited with {}",
+        name,
+        output.status
+    );
+    output.stdout
+}
+
+// Runs `src` through the brainfuck binary, however that ends.
+fn interpreted(name: &str, src: &str, args: &[&str], input: &[u8]) -> Output {
+    let file = std::env::temp_dir().join(format!("{}-{}.bf", name, std::process::id()));
+    std::fs::write(&file, src).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .arg(&file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&file).unwrap();
+    output
+}
+
+#[test]
+fn ir_runs_hello_world() {
+    let src = std::fs::read_to_string("hello-world.bf").unwrap();
+    assert_eq!(run("hello", &src, &["-e", "ir"], b""), b"Hello, World!\n");
+}
+
+#[test]
+fn bytecode_is_the_default_engine() {
+    let src = std::fs::read_to_string("hello-world.bf").unwrap();
+    assert_eq!(run("default", &src, &[], b""), b"Hello, World!\n");
+}
+
+#[test]
+fn ir_prints_raw_bytes() {
+    // 200 is not valid UTF-8 on its own and must come out untouched.
+    let src = "++++++++++[>++++++++++++++++++++<-]>.";
+    assert_eq!(run("raw", src, &["-e", "ir"], b""), [200]);
+}
+
+#[test]
+fn ir_echoes_input() {
+    let src = ",[.,]";
+    assert_eq!(run("echo", src, &["-e", "ir"], b"abc\0"), b"abc");
+}
+
+#[test]
+fn ir_handles_nested_and_scan_loops() {
+    // Counts down from 3 while shifting the counter right each time.
+    let src = "+++[[->+<]>-++++++++++++++++++++++++++++++++++++++++++++++++.------------------------------------------------]";
+    assert_eq!(run("scan", src, &["-e", "ir"], b""), b"210");
+}
+
+// Builds `src` with `--compile` and runs the executable, unless this machine
+// can't run it, as 64-bit kernels without i386 support can't for x86.
+fn compiled(name: &str, src: &str, args: &[&str], input: &[u8]) -> Option<Vec<u8>> {
+    executed(name, src, args, input).map(|output| stdout_of(name, output))
+}
+
+// Same as `compiled`, however the executable ends.
+fn executed(name: &str, src: &str, args: &[&str], input: &[u8]) -> Option<Output> {
+    let dir = std::env::temp_dir();
+    let exe = dir.join(format!("{}-{}-c", name, std::process::id()));
+    let file = exe.with_extension("bf");
+    std::fs::write(&file, src).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .arg("-c")
+        .arg(&file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::fs::remove_file(&file).unwrap();
+
+    let child = Command::new(&exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Err(e) if e.raw_os_error() == Some(8) => {
+            // ENOEXEC
+            std::fs::remove_file(&exe).unwrap();
+            return None;
+        }
+        child => child.unwrap(),
+    };
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&exe).unwrap();
+    Some(output)
+}
+
+// Translates `src` to source for `target` (`c` or `rust`), builds it with
+// `compiler` and runs it, unless the compiler isn't installed.
+fn translated(
+    name: &str,
+    src: &str,
+    args: &[&str],
+    input: &[u8],
+    target: &str,
+    compiler: &str,
+) -> Option<Output> {
+    if Command::new(compiler).arg("--version").output().is_err() {
+        return None;
+    }
+    let args = [args, &["--target", target]].concat();
+    let exe = std::env::temp_dir().join(format!("{}-{}-{}", name, std::process::id(), target));
+    let file = exe.with_extension("bf");
+    let source = exe.with_extension(if target == "rust" { "rs" } else { target });
+    std::fs::write(&file, src).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(&args)
+        .arg("-c")
+        .arg(&file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::fs::remove_file(&file).unwrap();
+    let status = Command::new(compiler)
+        .arg(&source)
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} didn't compile", source.display());
+    std::fs::remove_file(&source).unwrap();
+
+    let mut child = Command::new(&exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&exe).unwrap();
+    Some(output)
+}
+
+// Runs a WebAssembly module, the file after it on the command line, under
+// Node's WASI, exiting with the module's exit code.
+const WASI_RUNNER: &str = "
+    const { WASI } = require('node:wasi');
+    const wasi = new WASI({ version: 'preview1', returnOnExit: true });
+    const wasm = new WebAssembly.Module(require('fs').readFileSync(process.argv[1]));
+    process.exitCode = wasi.start(new WebAssembly.Instance(wasm, wasi.getImportObject()));
+";
+
+// Builds `src` for `target` (`wasm` or `js`) and runs the module with Node,
+// unless Node isn't installed.
+fn on_node(name: &str, src: &str, args: &[&str], input: &[u8], target: &str) -> Option<Output> {
+    if Command::new("node").arg("--version").output().is_err() {
+        return None;
+    }
+    let args = [args, &["--target", target]].concat();
+    let module = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let module = module.with_extension(if target == "js" { "mjs" } else { target });
+    let file = module.with_extension("bf");
+    std::fs::write(&file, src).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(&args)
+        .arg("-c")
+        .arg(&file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::fs::remove_file(&file).unwrap();
+
+    let runner: &[&str] = match target {
+        "wasm" => &["--no-warnings", "-e", WASI_RUNNER],
+        _ => &[],
+    };
+    let mut child = Command::new("node")
+        .args(runner)
+        .arg(&module)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&module).unwrap();
+    Some(output)
+}
+
+// Every backend must produce exactly `expected` for `src`.
+fn agree(name: &str, src: &str, input: &[u8], expected: &[u8]) {
+    agree_with(name, src, &[], input, expected);
+}
+
+// Same as `agree`, passing `args` to every run.
+fn agree_with(name: &str, src: &str, args: &[&str], input: &[u8], expected: &[u8]) {
+    for (way, output) in outcomes(name, src, args, input) {
+        assert_eq!(stdout_of(way, output), expected, "{}", way);
+    }
+}
+
+// Every backend must stop `src` with a runtime error, after printing exactly
+// `expected`.
+fn trap_everywhere(name: &str, src: &str, args: &[&str], expected: &[u8]) {
+    for (way, output) in outcomes(name, src, args, b"") {
+        assert_eq!(
+            output.status.code(),
+            Some(1),
+            "{} exited with {}",
+            way,
+            output.status
+        );
+        assert_eq!(output.stdout, expected, "{}", way);
+    }
+}
+
+// How running `src` went on every engine and backend this machine has.
+fn outcomes(name: &str, src: &str, args: &[&str], input: &[u8]) -> Vec<(&'static str, Output)> {
+    let mut outcomes = Vec::new();
+    for engine in ["tokens", "ir", "bytecode"] {
+        let args = [args, &["-e", engine]].concat();
+        outcomes.push((engine, interpreted(name, src, &args, input)));
+    }
+    if cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        let args = [args, &["--jit"]].concat();
+        outcomes.push(("jit", interpreted(name, src, &args, input)));
+    }
+    outcomes.extend(executed(name, src, args, input).map(|output| ("compiled", output)));
+    outcomes.extend(translated(name, src, args, input, "c", "cc").map(|output| ("c", output)));
+    outcomes
+        .extend(translated(name, src, args, input, "rust", "rustc").map(|output| ("rust", output)));
+    outcomes.extend(on_node(name, src, args, input, "wasm").map(|output| ("wasm", output)));
+    outcomes.extend(on_node(name, src, args, input, "js").map(|output| ("js", output)));
+    outcomes
+}
+
+#[test]
+fn leading_loop_is_skipped() {
+    // The first cell is zero, so the body must never run.
+    agree("leading", "[.+.]++++++++[>++++++++<-]>+.", b"", b"A");
+}
+
+#[test]
+fn zero_guarded_loops_are_skipped() {
+    agree(
+        "guarded",
+        "[-]>[->+<]>[[.]]+++++++++[<+++++++>-]<.",
+        b"",
+        b"?",
+    );
+}
+
+#[test]
+fn skipped_loops_nest() {
+    agree("nested", "+[-[.[.].]>++++++[<++++++++>-]<.[-]]", b"", b"0");
+}
+
+#[test]
+fn scan_loops_agree() {
+    agree("scans", ">+>+>+>+[<]>[>]++++++++[<++++++>-]<.", b"", b"1");
+}
+
+#[test]
+fn cells_wrap_around() {
+    agree("wrap", "-.+.", b"", &[255, 0]);
+}
+
+#[test]
+fn hello_world_agrees() {
+    let src = std::fs::read_to_string("hello-world.bf").unwrap();
+    agree("hello-all", &src, b"", b"Hello, World!\n");
+}
+
+#[test]
+fn input_agrees() {
+    agree("input", ",[.,]", b"xyz\0", b"xyz");
+}
+
+#[test]
+fn eof_leaves_cell_unchanged_by_default() {
+    agree("eof-default", "+++,.", b"", &[3]);
+}
+
+#[test]
+fn eof_policies_agree() {
+    let src = "+++,.>,.";
+    agree_with(
+        "eof-unchanged",
+        src,
+        &["--eof", "unchanged"],
+        b"a",
+        &[b'a', 0],
+    );
+    agree_with("eof-zero", src, &["--eof", "zero"], b"a", &[b'a', 0]);
+    agree_with("eof-max", src, &["--eof", "max"], b"", &[255, 255]);
+}
+
+#[test]
+fn sixteen_bit_cells_agree() {
+    // Prints `1` only if the cell can hold 256.
+    let src = "++++++++++++++++[>++++++++++++++++<-]>[[-]<+++++++[>+++++++<-]>.[-]]";
+    agree_with("cell16", src, &["--cell", "16"], b"", b"1");
+    agree_with("cell8", src, &["--cell", "8"], b"", b"");
+}
+
+#[test]
+fn tape_options_agree() {
+    let src = "+<<<<<<<<<<+>>>>>>>>>>.<<<<<<<<<<.";
+    agree_with("grow", src, &["--grow"], b"", &[1, 1]);
+    agree_with("short", "+>>>.", &["--tape", "3"], b"", &[1]);
+    agree_with(
+        "short-left",
+        "+>>>.<<<<-.",
+        &["--tape", "3"],
+        b"",
+        &[1, 255],
+    );
+    agree_with(
+        "short-trap",
+        "+>+>+<<[.>]",
+        &["--tape", "4", "--bounds", "trap"],
+        b"",
+        &[1, 1, 1],
+    );
+}
+
+#[test]
+fn trapping_overflow_agrees_in_range() {
+    let src = "+++[>+++++++++++++++++<-]>.[-]-";
+    agree_with("overflow-wrap", src, &["--overflow", "wrap"], b"", &[51]);
+    let src = "+++[>+++++++++++++++++<-]>.[-]";
+    agree_with("overflow-trap", src, &["--overflow", "trap"], b"", &[51]);
+}
+
+#[test]
+fn folded_runs_still_overflow() {
+    let args = ["--overflow", "trap"];
+    trap_everywhere("overflow-dip", "-+.", &args, b"");
+    trap_everywhere(
+        "overflow-peak-16",
+        "-+-",
+        &["--overflow", "trap", "--cell", "16"],
+        b"",
+    );
+    let src = "+++++++++++++++[>+++++++++++++++++<-]>.+-";
+    trap_everywhere("overflow-max", src, &args, &[255]);
+    agree_with(
+        "overflow-max-ok",
+        src.trim_end_matches("+-"),
+        &args,
+        b"",
+        &[255],
+    );
+}
+
+#[test]
+fn folded_moves_still_leave_the_tape() {
+    let args = ["--bounds", "trap", "--tape", "3"];
+    trap_everywhere("bounds-left", "<>+.", &args, b"");
+    trap_everywhere("bounds-right", "+.>>><<<.", &args, &[1]);
+    trap_everywhere("bounds-loop", "+[>>>-<<<-]", &args, b"");
+    agree_with("bounds-ok", "+>>+<<.>++>.<<.", &args, b"", &[1, 1, 1]);
+}
+
+#[test]
+fn trapping_bounds_without_moves_agree() {
+    // Nothing is ever checked, so there is nothing to trap on.
+    agree_with("bounds-unused", "+.", &["--bounds", "trap"], b"", &[1]);
+    let args = ["--bounds", "trap", "--target", "x86_64"];
+    let out = compiled("bounds-unused-64", "+.", &args, b"");
+    assert_eq!(out, Some(vec![1]));
+}
+
+#[test]
+fn standard_lexer_ignores_comments() {
+    let src = "Prints A: ++++++++[>++++++++<-]>+. Done!";
+    agree_with("lexer-standard", src, &["--lexer", "standard"], b"", b"A");
+}
+
+#[test]
+fn x86_64_target_agrees() {
+    // The interpreters don't have a target, so only the executable runs.
+    let agree_64 = |name: &str, src: &str, args: &[&str], input: &[u8], expected: &[u8]| {
+        let args = [args, &["--target", "x86_64"]].concat();
+        let out = compiled(name, src, &args, input).unwrap_or(expected.to_vec());
+        assert_eq!(out, expected, "{}", name);
+    };
+    let src = std::fs::read_to_string("hello-world.bf").unwrap();
+    agree_64("hello-64", &src, &[], b"", b"Hello, World!\n");
+    agree_64("input-64", ",[.,]", &[], b"xyz\0", b"xyz");
+    let src = "+<<<<<<<<<<+>>>>>>>>>>.<<<<<<<<<<.";
+    agree_64("grow-64", src, &["--grow"], b"", &[1, 1]);
+    let args = ["--tape", "4", "--bounds", "trap"];
+    agree_64("trap-64", "+>+>+<<[.>]", &args, b"", &[1, 1, 1]);
+    let args = ["--cell", "16", "--eof", "max"];
+    agree_64("cell16-64", "-.,.", &args, b"", &[255, 255]);
+}
+
+#[test]
+fn targets_need_compile() {
+    // Stops with the help instead of running the program.
+    let output = interpreted("target-run", "+.", &["--target", "x86_64"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.ends_with(b"Prints this message\n"));
+}
+
+#[test]
+fn external_assemblers_agree() {
+    for (assembler, tools) in [
+        ("fasm", &["fasm"][..]),
+        ("nasm", &["nasm", "ld"]),
+        ("gas", &["as", "ld"]),
+        ("gas-att", &["as", "ld"]),
+    ] {
+        if tools
+            .iter()
+            .any(|tool| Command::new(tool).arg("--version").output().is_err())
+        {
+            continue;
+        }
+        for target in ["x86", "x86_64"] {
+            let args = ["--assembler", assembler, "--target", target];
+            let src = std::fs::read_to_string("hello-world.bf").unwrap();
+            let expected = b"Hello, World!\n";
+            let out = compiled("hello-as", &src, &args, b"").unwrap_or(expected.to_vec());
+            assert_eq!(out, expected, "{} {}", assembler, target);
+
+            let args = [
+                &args[..],
+                &["--cell", "16", "--eof", "max", "--bounds", "trap"],
+            ]
+            .concat();
+            let out = compiled("input-as", ">,.,.", &args, b"a").unwrap_or(vec![b'a', 255]);
+            assert_eq!(out, [b'a', 255], "{} {}", assembler, target);
+        }
+    }
+}
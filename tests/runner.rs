@@ -0,0 +1,274 @@
+use std::io;
+
+use brainfuck::{
+    BoundsPolicy, CellWidth, Config, EofPolicy, Error, OverflowPolicy, Program, Runner,
+    RuntimeError, Target,
+};
+
+fn both(src: &str, input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let program = Program::parse(src).unwrap();
+
+    let mut tokens = Vec::new();
+    program.run_tokens_with(input, &mut tokens).unwrap();
+    let mut ir = Vec::new();
+    program.run_with(input, &mut ir).unwrap();
+
+    (tokens, ir)
+}
+
+#[test]
+fn captures_output_in_memory() {
+    let src = std::fs::read_to_string("hello-world.bf").unwrap();
+    let (tokens, ir) = both(&src, b"");
+    assert_eq!(tokens, b"Hello, World!\n");
+    assert_eq!(ir, b"Hello, World!\n");
+}
+
+#[test]
+fn feeds_scripted_input() {
+    // Reverses its input up to the first zero byte.
+    let (tokens, ir) = both(">,[>,]<[.<]", b"stressed\0");
+    assert_eq!(tokens, b"desserts");
+    assert_eq!(ir, b"desserts");
+}
+
+#[test]
+fn hands_back_the_output_stream() {
+    let program = Program::parse("+++[>++++++++++++++++<-]>.").unwrap();
+    let mut runner = Runner::with_io(vec![], vec![], io::empty(), Vec::new());
+    runner.run_ins(&program.optimize()).unwrap();
+    assert_eq!(runner.into_output().unwrap(), b"0");
+}
+
+#[test]
+fn writes_to_files() {
+    let path = std::env::temp_dir().join(format!("runner-out-{}", std::process::id()));
+    let file = std::fs::File::create(&path).unwrap();
+    Program::parse("-.")
+        .unwrap()
+        .run_with(io::empty(), file)
+        .unwrap();
+
+    let written = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written, [255]);
+}
+
+#[test]
+fn end_of_input_follows_the_eof_policy() {
+    let program = Program::parse("++,.").unwrap();
+
+    for (eof, expected) in [
+        (EofPolicy::Unchanged, 2),
+        (EofPolicy::Zero, 0),
+        (EofPolicy::Max, 255),
+    ] {
+        let program = program.clone().with_config(Config {
+            eof,
+            ..Default::default()
+        });
+        let mut tokens = Vec::new();
+        program.run_tokens_with(io::empty(), &mut tokens).unwrap();
+        let mut ir = Vec::new();
+        program.run_with(io::empty(), &mut ir).unwrap();
+
+        assert_eq!(tokens, [expected], "{:?}", eof);
+        assert_eq!(ir, [expected], "{:?}", eof);
+    }
+}
+
+fn run_cells(src: &str, cell: CellWidth) -> Vec<u8> {
+    let program = Program::parse(src).unwrap().with_config(Config {
+        cell,
+        ..Default::default()
+    });
+    let mut out = Vec::new();
+    program.run_with(io::empty(), &mut out).unwrap();
+    out
+}
+
+#[test]
+fn wider_cells_hold_more() {
+    // Prints `1` if the second cell ends up non-zero after adding 256.
+    let src = "++++++++++++++++[>++++++++++++++++<-]>[[-]<+++++++[>+++++++<-]>.[-]]";
+    assert_eq!(run_cells(src, CellWidth::U8), b"");
+    assert_eq!(run_cells(src, CellWidth::U16), b"1");
+    assert_eq!(run_cells(src, CellWidth::U32), b"1");
+    assert_eq!(run_cells(src, CellWidth::Unbounded), b"1");
+}
+
+#[test]
+fn unbounded_cells_go_past_32_bits() {
+    // 1024 * 1024 * 4096 is 0 in a 32-bit cell. The last loop leaves by
+    // stepping onto an empty cell, as clearing 2^32 + 49 one at a time would
+    // take a while.
+    let (k, k4) = ("+".repeat(1024), "+".repeat(4096));
+    let src = format!("{k}[>{k}<-]>[>{k4}<-]>[>+++++++[<+++++++>-]<.>]");
+    assert_eq!(run_cells(&src, CellWidth::U32), b"");
+    assert_eq!(run_cells(&src, CellWidth::Unbounded), b"1");
+}
+
+#[test]
+fn cells_print_their_low_byte() {
+    assert_eq!(run_cells("-.", CellWidth::U8), [255]);
+    assert_eq!(run_cells("-.", CellWidth::U16), [255]);
+    assert_eq!(run_cells("-.", CellWidth::Unbounded), [255]);
+    assert_eq!(run_cells(&("+".repeat(321) + "."), CellWidth::U16), [65]);
+}
+
+#[test]
+fn eof_max_depends_on_the_cell_width() {
+    // After `,` hits EOF, adding one gives 0 only if the cell held -1.
+    let src = ",+[>+++++++[<+++++++>-]<.[-]]";
+    for cell in [
+        CellWidth::U8,
+        CellWidth::U16,
+        CellWidth::U32,
+        CellWidth::Unbounded,
+    ] {
+        let program = Program::parse(src).unwrap().with_config(Config {
+            eof: EofPolicy::Max,
+            cell,
+            ..Default::default()
+        });
+        let mut out = Vec::new();
+        program.run_with(io::empty(), &mut out).unwrap();
+        assert_eq!(out, b"", "{:?}", cell);
+    }
+}
+
+#[test]
+fn unbounded_cells_cannot_be_compiled() {
+    let program = Program::parse("+.").unwrap().with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    });
+    assert!(program.assembly().is_err());
+}
+
+fn run_tape(src: &str, tape_len: usize, bounds: BoundsPolicy) -> (Vec<u8>, Vec<u8>) {
+    let program = Program::parse(src).unwrap().with_config(Config {
+        tape_len,
+        bounds,
+        ..Default::default()
+    });
+
+    let mut tokens = Vec::new();
+    program.run_tokens_with(io::empty(), &mut tokens).unwrap();
+    let mut ir = Vec::new();
+    program.run_with(io::empty(), &mut ir).unwrap();
+    (tokens, ir)
+}
+
+#[test]
+fn short_tapes_wrap_around() {
+    let (tokens, ir) = run_tape("+>>>.<<<<<<+.", 3, BoundsPolicy::Wrap);
+    assert_eq!(tokens, [1, 2]);
+    assert_eq!(ir, [1, 2]);
+}
+
+#[test]
+fn growing_tapes_extend_both_ways() {
+    // Writes five cells left and right of a one cell tape, then reads them
+    // back from the other side.
+    let src = "<<<<<+++>>>>>>>>>>++<<<<<<<<<<.>>>>>>>>>>.";
+    let (tokens, ir) = run_tape(src, 1, BoundsPolicy::Grow);
+    assert_eq!(tokens, [3, 2]);
+    assert_eq!(ir, [3, 2]);
+}
+
+#[test]
+fn growing_tapes_keep_their_contents() {
+    // Leaves a trail of cells going left, each growth moving them in memory,
+    // then prints them walking back right.
+    let src = format!("{}[.>]", "+<".repeat(100) + ">");
+    let (tokens, ir) = run_tape(&src, 2, BoundsPolicy::Grow);
+    assert_eq!(tokens, vec![1; 100]);
+    assert_eq!(ir, vec![1; 100]);
+}
+
+#[test]
+fn trapping_tapes_point_at_the_code() {
+    let program = Program::parse("+>\n<<<.").unwrap().with_config(Config {
+        tape_len: 3,
+        bounds: BoundsPolicy::Trap,
+        ..Default::default()
+    });
+
+    // Both stop at the `<` that leaves the tape.
+    for err in [
+        program.run_tokens_with(io::empty(), Vec::new()),
+        program.run_with(io::empty(), Vec::new()),
+    ] {
+        let Err(Error::Runtime(RuntimeError::OutOfBounds { span, cell, .. })) = err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!((span.line, span.col, cell), (2, 2, -1));
+    }
+}
+
+#[test]
+fn trapping_tapes_run_programs_that_stay_on_them() {
+    let (tokens, ir) = run_tape("+>+>+<<[.>]", 4, BoundsPolicy::Trap);
+    assert_eq!(tokens, [1, 1, 1]);
+    assert_eq!(ir, [1, 1, 1]);
+}
+
+fn trap_overflow(src: &str, cell: CellWidth) -> (Result<(), Error>, Result<(), Error>) {
+    let program = Program::parse(src).unwrap().with_config(Config {
+        cell,
+        overflow: OverflowPolicy::Trap,
+        ..Default::default()
+    });
+    (
+        program.run_tokens_with(io::empty(), Vec::new()),
+        program.run_with(io::empty(), Vec::new()),
+    )
+}
+
+// Where an overflow was reported and whether it went below zero.
+fn overflow(result: Result<(), Error>) -> (usize, usize, bool) {
+    match result {
+        Err(Error::Runtime(RuntimeError::Overflow {
+            span, below_zero, ..
+        })) => (span.line, span.col, below_zero),
+        other => panic!("expected an overflow, got {:?}", other),
+    }
+}
+
+#[test]
+fn trapping_cells_point_at_the_code() {
+    // The token engine stops at the `-` that goes below 0, the IR one at the
+    // start of the run it was merged into. Runs are split where they turn
+    // around, so that is the first `-`, not the `+`.
+    let (tokens, ir) = trap_overflow(">+\n--", CellWidth::U8);
+    assert_eq!(overflow(tokens), (2, 2, true));
+    assert_eq!(overflow(ir), (2, 1, true));
+
+    let (tokens, ir) = trap_overflow(&"+".repeat(256), CellWidth::U8);
+    assert_eq!(overflow(tokens), (1, 256, false));
+    assert_eq!(overflow(ir), (1, 1, false));
+}
+
+#[test]
+fn trapping_cells_allow_their_whole_range() {
+    let (tokens, ir) = trap_overflow(&("+".repeat(256) + &"-".repeat(256)), CellWidth::U16);
+    tokens.unwrap();
+    ir.unwrap();
+    let (tokens, ir) = trap_overflow("-", CellWidth::Unbounded);
+    tokens.unwrap();
+    ir.unwrap();
+}
+
+#[test]
+fn x86_64_target_uses_64_bit_syscalls() {
+    let program = Program::parse("+[>,.<-]").unwrap().with_config(Config {
+        target: Target::X86_64,
+        ..Default::default()
+    });
+    let asm = program.assembly().unwrap();
+    assert!(asm.starts_with("format ELF64 executable 3"));
+    assert!(asm.contains("syscall"));
+    assert!(asm.contains("lea rbp, [tape]"));
+    assert!(!asm.contains("int 0x80"));
+}
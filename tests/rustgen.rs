@@ -0,0 +1,110 @@
+use std::process::{Command, Stdio};
+
+use brainfuck::{
+    rustgen, BoundsPolicy, CellWidth, CodegenError, Config, EofPolicy, Error, OverflowPolicy,
+    Program,
+};
+
+fn rust_source(src: &str, config: Config) -> String {
+    Program::parse(src)
+        .unwrap()
+        .with_config(config)
+        .rust_source()
+        .unwrap()
+}
+
+#[test]
+fn options_show_up_in_the_source() {
+    let src = rust_source(
+        ",>.",
+        Config {
+            cell: CellWidth::U32,
+            tape_len: 100,
+            eof: EofPolicy::Zero,
+            ..Default::default()
+        },
+    );
+    assert!(src.contains("const TAPE_LEN: usize = 100;\n"));
+    assert!(src.contains("type Cell = u32;\n"));
+    assert!(src.contains("=> self.tape[i] = 0,"));
+    assert!(src.contains("    m.read(0)?;\n    m.print(1)?;\n    m.shift(1);\n"));
+    assert!(!src.contains("unsafe"));
+}
+
+#[test]
+fn trapping_code_says_where_it_is() {
+    let src = rust_source(
+        "+\n>-",
+        Config {
+            bounds: BoundsPolicy::Trap,
+            overflow: OverflowPolicy::Trap,
+            ..Default::default()
+        },
+    );
+    assert!(src.contains("    m.add(0, 1, \"line 1, col 1\");\n"));
+    assert!(src.contains("    m.shift(1, \"line 2, col 1\");\n"));
+}
+
+#[test]
+fn unbounded_cells_are_unsupported() {
+    let program = Program::parse("+.").unwrap().with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    });
+    let err = program.rust_source().unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+}
+
+#[test]
+fn manifests_have_valid_package_names() {
+    assert!(rustgen::manifest("hello").contains("name = \"hello\"\n"));
+    assert!(rustgen::manifest("Hello World").contains("name = \"hello-world\"\n"));
+    assert!(rustgen::manifest("99-bottles").contains("name = \"bf-99-bottles\"\n"));
+}
+
+#[test]
+fn traps_report_like_the_interpreter() {
+    if Command::new("rustc").arg("--version").output().is_err() {
+        return;
+    }
+    for bounds in [BoundsPolicy::Trap, BoundsPolicy::Grow] {
+        let config = Config {
+            bounds,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        };
+        for src in ["+>\n-", "<<+>-"] {
+            let program = Program::parse(src).unwrap().with_config(config);
+            let expected = format!(
+                "{}\n",
+                program
+                    .run_with(std::io::empty(), std::io::sink())
+                    .unwrap_err()
+            );
+
+            let base = std::env::temp_dir().join(format!(
+                "rustgen-trap-{:?}-{}-{}",
+                bounds,
+                src.len(),
+                std::process::id()
+            ));
+            let source = base.with_extension("rs");
+            std::fs::write(&source, program.rust_source().unwrap()).unwrap();
+            let output = Command::new("rustc")
+                .arg(&source)
+                .arg("-o")
+                .arg(&base)
+                .output()
+                .unwrap();
+            std::fs::remove_file(&source).unwrap();
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8_lossy(&output.stderr), "", "warnings");
+
+            let output = Command::new(&base).stdin(Stdio::null()).output().unwrap();
+            std::fs::remove_file(&base).unwrap();
+            assert_eq!(output.status.code(), Some(1), "{:?} {}", bounds, src);
+            assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+        }
+    }
+}
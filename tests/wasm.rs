@@ -0,0 +1,118 @@
+use std::process::{Command, Stdio};
+
+use brainfuck::{
+    BoundsPolicy, CellWidth, CodegenError, Config, Error, Loops, OverflowPolicy, Program,
+};
+
+const WASI_RUNNER: &str = "
+    const { WASI } = require('node:wasi');
+    const wasi = new WASI({ version: 'preview1', returnOnExit: true });
+    const wasm = new WebAssembly.Module(require('fs').readFileSync(process.argv[1]));
+    process.exitCode = wasi.start(new WebAssembly.Instance(wasm, wasi.getImportObject()));
+";
+
+fn wat(src: &str, config: Config) -> String {
+    Program::parse(src)
+        .unwrap()
+        .with_config(config)
+        .wat()
+        .unwrap()
+}
+
+#[test]
+fn modules_export_memory_and_start() {
+    let wat = wat("+.", Config::default());
+    assert!(wat.starts_with("(module\n"));
+    assert!(wat.contains("(memory (export \"memory\") 1)"));
+    assert!(wat.contains("(func $_start (export \"_start\")"));
+    assert!(wat.contains("(import \"wasi_snapshot_preview1\" \"fd_write\""));
+}
+
+#[test]
+fn binaries_have_the_wasm_header() {
+    let wasm = Program::parse("+[-]").unwrap().wasm().unwrap();
+    assert_eq!(wasm[..8], *b"\0asm\x01\0\0\0");
+}
+
+#[test]
+fn unused_helpers_are_left_out() {
+    let wat = wat("+.", Config::default());
+    assert!(wat.contains("(func $putc"));
+    assert!(!wat.contains("(func $getc"));
+    assert!(!wat.contains("(func $seek"));
+    assert!(!wat.contains("(func $trap"));
+}
+
+#[test]
+fn loops_are_labelled_in_order() {
+    let wat = wat("+[>[-]<-]", Config::default());
+    let outer = wat.find("block $loop_end_0").unwrap();
+    let inner = wat.find("block $loop_end_1").unwrap();
+    assert!(outer < inner);
+    assert!(wat.contains("br $loop_start_1"));
+
+    let mut loops = Loops::new();
+    assert_eq!(loops.start(), 0);
+    assert_eq!(loops.start(), 1);
+    assert_eq!(loops.end(), 1);
+    assert_eq!(loops.start(), 2);
+    assert_eq!(loops.end(), 2);
+    assert_eq!(loops.end(), 0);
+}
+
+#[test]
+fn unbounded_cells_are_unsupported() {
+    let program = Program::parse("+.").unwrap().with_config(Config {
+        cell: CellWidth::Unbounded,
+        ..Default::default()
+    });
+    let err = program.wasm().unwrap_err();
+    assert!(matches!(err, Error::Codegen(CodegenError::Unsupported(_))));
+}
+
+#[test]
+fn traps_exit_with_a_message() {
+    if Command::new("node").arg("--version").output().is_err() {
+        return;
+    }
+    for (src, bounds, expected) in [
+        (
+            "+>>+",
+            BoundsPolicy::Trap,
+            "Runtime error: Pointer out of bounds at line 1, col 3\n",
+        ),
+        (
+            "+>\n-<-",
+            BoundsPolicy::Trap,
+            "Runtime error: Cell overflow at line 2, col 1: cell went below 0\n",
+        ),
+        (
+            "<<-",
+            BoundsPolicy::Grow,
+            "Runtime error: Cell overflow at line 1, col 3: cell went below 0\n",
+        ),
+    ] {
+        let program = Program::parse(src).unwrap().with_config(Config {
+            bounds,
+            overflow: OverflowPolicy::Trap,
+            tape_len: 2,
+            ..Default::default()
+        });
+        let module = std::env::temp_dir().join(format!(
+            "wasm-trap-{}-{}.wasm",
+            src.len(),
+            std::process::id()
+        ));
+        std::fs::write(&module, program.wasm().unwrap()).unwrap();
+
+        let output = Command::new("node")
+            .args(["--no-warnings", "-e", WASI_RUNNER])
+            .arg(&module)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        std::fs::remove_file(&module).unwrap();
+        assert_eq!(output.status.code(), Some(1), "{}", src);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+    }
+}